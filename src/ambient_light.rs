@@ -0,0 +1,290 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+const IIO_DIR: &str = "/sys/bus/iio/devices";
+/// Weight of the newest sample in the smoothed illuminance
+const LUX_SMOOTHING: f64 = 0.2;
+/// Brightness read back can be off by one from what we wrote because of rounding
+const ROUNDING_TOLERANCE: i16 = 1;
+
+/// An IIO ambient light sensor, e.g. `/sys/bus/iio/devices/iio:device0`
+#[derive(Debug, Clone)]
+pub struct LightSensor {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+impl LightSensor {
+    fn find_in(dir: &Path) -> Option<LightSensor> {
+        let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+            .ok()?
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| {
+                path.join("in_illuminance_raw").exists()
+                    || path.join("in_illuminance_input").exists()
+            })
+            .collect();
+        paths.sort();
+        let path = paths.into_iter().next()?;
+        let name = fs::read_to_string(path.join("name"))
+            .map(|name| name.trim().to_string())
+            .unwrap_or_else(|_| path.file_name().unwrap().to_string_lossy().into_owned());
+        Some(LightSensor { name, path })
+    }
+
+    /// The first sensor reporting illuminance, `None` if there is none
+    pub fn find() -> Option<LightSensor> {
+        LightSensor::find_in(Path::new(IIO_DIR))
+    }
+
+    fn read_f64(&self, file: &str) -> Option<f64> {
+        fs::read_to_string(self.path.join(file))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    }
+
+    /// Illuminance in lux. Drivers either report it directly in `in_illuminance_input`,
+    /// or as `(in_illuminance_raw + in_illuminance_offset) * in_illuminance_scale`.
+    pub fn read_lux(&self) -> Option<f64> {
+        if let Some(lux) = self.read_f64("in_illuminance_input") {
+            return Some(lux);
+        }
+        let raw = self.read_f64("in_illuminance_raw")?;
+        let offset = self.read_f64("in_illuminance_offset").unwrap_or(0.0);
+        let scale = self.read_f64("in_illuminance_scale").unwrap_or(1.0);
+        Some((raw + offset) * scale)
+    }
+}
+
+/// Map illuminance to brightness percent, linearly interpolated between points
+/// on a logarithmic lux scale, since our eyes perceive light logarithmically
+#[derive(Debug, Clone, PartialEq)]
+pub struct LuxCurve {
+    /// (lux, percent), sorted by lux
+    points: Vec<(f64, f64)>,
+}
+
+impl LuxCurve {
+    pub fn new(mut points: Vec<(f64, f64)>) -> Result<Self, String> {
+        if points.is_empty() {
+            return Err("The curve needs at least one point".to_string());
+        }
+        if let Some(&(lux, percent)) = points
+            .iter()
+            .find(|&&(lux, percent)| lux < 0.0 || !(0.0..=100.0).contains(&percent))
+        {
+            return Err(format!("Invalid point {}:{}", lux, percent));
+        }
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Ok(LuxCurve { points })
+    }
+
+    pub fn percent_at(&self, lux: f64) -> f64 {
+        let log = |lux: f64| (lux.max(0.0) + 1.0).ln();
+        let first = self.points[0];
+        let last = self.points[self.points.len() - 1];
+        if lux <= first.0 {
+            return first.1;
+        }
+        if lux >= last.0 {
+            return last.1;
+        }
+        let window = self
+            .points
+            .windows(2)
+            .find(|pair| lux <= pair[1].0)
+            .expect("lux is inside the curve");
+        let ((low_lux, low_percent), (high_lux, high_percent)) = (window[0], window[1]);
+        let ratio = (log(lux) - log(low_lux)) / (log(high_lux) - log(low_lux));
+        low_percent + (high_percent - low_percent) * ratio
+    }
+}
+
+impl Default for LuxCurve {
+    /// From a dark room to direct sunlight
+    fn default() -> Self {
+        LuxCurve::new(vec![
+            (0.0, 5.0),
+            (10.0, 20.0),
+            (100.0, 40.0),
+            (1000.0, 70.0),
+            (10000.0, 100.0),
+        ])
+        .unwrap()
+    }
+}
+
+impl FromStr for LuxCurve {
+    type Err = String;
+
+    /// Parse `lux:percent,lux:percent,...`, e.g. `0:5,100:40,10000:100`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let points = s
+            .split(',')
+            .map(|point| {
+                let (lux, percent) = point
+                    .split_once(':')
+                    .ok_or_else(|| format!("Expected lux:percent, got {}", point))?;
+                let lux = lux
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("Invalid lux {}", lux))?;
+                let percent = percent
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("Invalid percent {}", percent))?;
+                Ok((lux, percent))
+            })
+            .collect::<Result<Vec<(f64, f64)>, String>>()?;
+        LuxCurve::new(points)
+    }
+}
+
+/// Decide the brightness from the ambient light. When the brightness is
+/// changed by someone else, the curve is shifted to keep that change instead
+/// of reverting it.
+#[derive(Debug, Clone)]
+pub struct AutoBrightness {
+    curve: LuxCurve,
+    /// Minimum difference in percent before changing the brightness
+    hysteresis: u8,
+    smoothed_lux: Option<f64>,
+    /// Shift applied to the curve, in percent
+    offset: f64,
+    /// The brightness we expect the backlight to be at
+    expected: Option<u8>,
+}
+
+impl AutoBrightness {
+    pub fn new(curve: LuxCurve, hysteresis: u8) -> Self {
+        AutoBrightness {
+            curve,
+            hysteresis,
+            smoothed_lux: None,
+            offset: 0.0,
+            expected: None,
+        }
+    }
+
+    pub fn offset(&self) -> f64 {
+        self.offset
+    }
+
+    /// Feed a new illuminance sample along with the current brightness,
+    /// return the brightness to set if it should change
+    pub fn update(&mut self, lux: f64, current: u8) -> Option<u8> {
+        let lux = match self.smoothed_lux {
+            Some(old) => old + LUX_SMOOTHING * (lux - old),
+            None => lux,
+        };
+        self.smoothed_lux = Some(lux);
+        let on_curve = self.curve.percent_at(lux);
+
+        if let Some(expected) = self.expected {
+            if (current as i16 - expected as i16).abs() > ROUNDING_TOLERANCE {
+                // Changed by hand, follow the user from now on
+                self.offset = current as f64 - on_curve;
+                self.expected = Some(current);
+                return None;
+            }
+        }
+
+        let target = (on_curve + self.offset).round().clamp(0.0, 100.0) as u8;
+        if (target as i16 - current as i16).unsigned_abs() < self.hysteresis as u16 {
+            self.expected.get_or_insert(current);
+            return None;
+        }
+        self.expected = Some(target);
+        Some(target)
+    }
+
+    /// Record the brightness read back after setting the one returned by
+    /// [`update`](Self::update). Coarse backlights can't reach every percent,
+    /// and the difference must not be taken for a manual change.
+    pub fn applied(&mut self, current: u8) {
+        self.expected = Some(current);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lux_is_computed_from_raw_and_scale() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let device = root.join("iio:device0");
+        fs::create_dir_all(&device).unwrap();
+        fs::write(device.join("name"), "als\n").unwrap();
+        fs::write(device.join("in_illuminance_raw"), "200\n").unwrap();
+        fs::write(device.join("in_illuminance_scale"), "0.5\n").unwrap();
+
+        let sensor = LightSensor::find_in(root).unwrap();
+        assert_eq!(sensor.name, "als");
+        assert_eq!(sensor.read_lux(), Some(100.0));
+    }
+
+    #[test]
+    fn curve_is_interpolated_on_log_scale() {
+        let curve: LuxCurve = "0:10, 99:50, 9999:90".parse().unwrap();
+        assert_eq!(curve.percent_at(0.0), 10.0);
+        assert_eq!(curve.percent_at(99.0), 50.0);
+        // ln(1000) is halfway between ln(100) and ln(10000)
+        assert!((curve.percent_at(999.0) - 70.0).abs() < 1e-9);
+        assert_eq!(curve.percent_at(1e6), 90.0);
+        assert!("0:101".parse::<LuxCurve>().is_err());
+        assert!("abc".parse::<LuxCurve>().is_err());
+    }
+
+    #[test]
+    fn small_changes_are_ignored() {
+        let curve = LuxCurve::new(vec![(0.0, 0.0), (100.0, 100.0)]).unwrap();
+        let mut auto = AutoBrightness::new(curve, 5);
+        assert_eq!(auto.update(0.0, 2), None);
+        // Smoothed to 20 lux
+        assert_eq!(auto.update(100.0, 2), Some(66));
+    }
+
+    #[test]
+    fn manual_change_shifts_the_curve() {
+        let curve = LuxCurve::new(vec![(0.0, 40.0)]).unwrap();
+        let mut auto = AutoBrightness::new(curve, 5);
+        assert_eq!(auto.update(0.0, 10), Some(40));
+        // The user turns it down, we don't fight back
+        assert_eq!(auto.update(0.0, 25), None);
+        assert_eq!(auto.offset(), -15.0);
+        assert_eq!(auto.update(0.0, 25), None);
+    }
+
+    #[test]
+    fn coarse_backlight_does_not_drift() {
+        use crate::brightness::{Backlight, BrightnessCurve};
+
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let dir = root.join("acpi_video0");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("type"), "firmware\n").unwrap();
+        fs::write(dir.join("brightness"), "0\n").unwrap();
+        fs::write(dir.join("max_brightness"), "15\n").unwrap();
+        let backlight = Backlight::find_in(root, None)
+            .unwrap()
+            .with_curve(BrightnessCurve::Cie);
+
+        let mut auto = AutoBrightness::new(LuxCurve::default(), 5);
+        for _ in 0..5 {
+            if let Some(target) = auto.update(100.0, backlight.get_brightness().unwrap()) {
+                assert_eq!(target, 40);
+                backlight.set_brightness(target).unwrap();
+                auto.applied(backlight.get_brightness().unwrap());
+            }
+        }
+        // 40% is between two raw levels
+        assert_eq!(backlight.get_brightness().unwrap(), 43);
+        assert_eq!(auto.offset(), 0.0);
+    }
+}
@@ -1,29 +1,853 @@
 use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
-#[derive(Debug, Default, Copy, Clone)]
+use crate::util::Percent;
+
+const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+
+/// Kind of a power supply, read from its `type` file
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SupplyType {
+    Battery,
+    Mains,
+    Usb,
+    Other,
+}
+
+impl SupplyType {
+    fn parse(value: &str) -> Self {
+        match value.trim() {
+            "Battery" => SupplyType::Battery,
+            "Mains" => SupplyType::Mains,
+            // The kernel also has USB_C, USB_PD, ... but newer ones all report "USB"
+            kind if kind.starts_with("USB") => SupplyType::Usb,
+            _ => SupplyType::Other,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PowerSupply {
+    /// Directory name under `/sys/class/power_supply`, e.g. `BAT0`, `AC`
+    pub name: String,
+    pub kind: SupplyType,
+    pub path: PathBuf,
+}
+
+impl PowerSupply {
+    fn read(&self, file: &str) -> Option<String> {
+        fs::read_to_string(self.path.join(file))
+            .ok()
+            .map(|content| content.trim().to_string())
+    }
+
+    fn read_unsigned(&self, file: &str) -> Option<u64> {
+        self.read(file)?.parse().ok()
+    }
+
+    /// Some drivers report `current_now`/`power_now` as negative when discharging
+    fn read_magnitude(&self, file: &str) -> Option<u64> {
+        self.read(file)?.parse::<i64>().ok().map(i64::unsigned_abs)
+    }
+
+    /// Whether an adapter is plugged in, always `false` for batteries
+    pub fn online(&self) -> bool {
+        self.kind != SupplyType::Battery && self.read("online").as_deref() == Some("1")
+    }
+}
+
+/// Which set of sysfs files the battery values came from
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum EnergySource {
+    /// `energy_*`, in µWh
+    #[default]
+    Energy,
+    /// `charge_*` in µAh, converted to µWh with the design voltage
+    Charge,
+    /// `charge_*` in µAh, left as is because the battery reports no voltage.
+    /// Percentage and wear are still correct, but the values can't be summed
+    /// with other batteries.
+    ChargeWithoutVoltage,
+}
+
+/// Content of the battery `status` file
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum ChargeStatus {
+    Charging,
+    Discharging,
+    /// Plugged in and fully charged
+    Full,
+    /// Plugged in but not charging, e.g. the charge threshold is reached
+    NotCharging,
+    #[default]
+    Unknown,
+}
+
+impl ChargeStatus {
+    fn parse(value: &str) -> Self {
+        match value.trim() {
+            "Charging" => ChargeStatus::Charging,
+            "Discharging" => ChargeStatus::Discharging,
+            "Full" => ChargeStatus::Full,
+            "Not charging" => ChargeStatus::NotCharging,
+            _ => ChargeStatus::Unknown,
+        }
+    }
+
+    /// Combine the status of several batteries, a pack being charged or
+    /// drained matters more than the idle ones
+    fn combine(statuses: impl Iterator<Item = ChargeStatus> + Clone) -> Self {
+        use ChargeStatus::*;
+        for status in [Charging, Discharging, NotCharging, Full] {
+            if statuses.clone().any(|s| s == status) {
+                return status;
+            }
+        }
+        Unknown
+    }
+}
+
+#[derive(Debug, Default, Clone)]
 pub struct BatteryInfo {
+    /// Name of the battery, e.g. `BAT0`, or the names joined by `+` for the aggregated view
+    pub name: String,
+    /// Energy values are in µWh, except for [`EnergySource::ChargeWithoutVoltage`]
     pub now: u64,
     pub full: u64,
     pub full_design: u64,
-    pub charging: bool,
-}
-
-fn read_stat(name: &str) -> u64 {
-    fs::read_to_string(format!("/sys/class/power_supply/BAT1/energy_{}", name))
-        .unwrap_or_else(|_| panic!("Read `energy_{}`", name))
-        .trim()
-        .parse()
-        .unwrap_or_else(|_| panic!("Convert `energy_{}` content to number", name))
-}
-
-pub fn get_battery_info() -> BatteryInfo {
-    BatteryInfo {
-        now: read_stat("now"),
-        full: read_stat("full"),
-        full_design: read_stat("full_design"),
-        charging: fs::read_to_string("/sys/class/power_supply/ACAD/online")
-            .expect("Read charging status")
-            .trim()
-            .eq("1"),
+    /// Current power draw in µW (µA for [`EnergySource::ChargeWithoutVoltage`]),
+    /// `None` if the driver doesn't report it
+    pub rate: Option<u64>,
+    pub status: ChargeStatus,
+    /// Whether any adapter is plugged in
+    pub ac_online: bool,
+    /// Charge cycles so far, `None` if the driver doesn't count them
+    pub cycle_count: Option<u64>,
+    pub source: EnergySource,
+}
+
+impl BatteryInfo {
+    /// Sum energy of all packs into a single battery. Packs in µAh can't be
+    /// added to packs in µWh, so they are left out of the sums when mixed.
+    pub fn aggregate(batteries: &[BatteryInfo]) -> BatteryInfo {
+        let convertible = |b: &BatteryInfo| b.source != EnergySource::ChargeWithoutVoltage;
+        let summed: Vec<&BatteryInfo> = if batteries.iter().any(convertible) {
+            batteries.iter().filter(|b| convertible(b)).collect()
+        } else {
+            batteries.iter().collect()
+        };
+        let names: Vec<&str> = summed.iter().map(|b| b.name.as_str()).collect();
+        BatteryInfo {
+            name: names.join("+"),
+            now: summed.iter().map(|b| b.now).sum(),
+            full: summed.iter().map(|b| b.full).sum(),
+            full_design: summed.iter().map(|b| b.full_design).sum(),
+            rate: summed.iter().map(|b| b.rate).sum(),
+            status: ChargeStatus::combine(batteries.iter().map(|b| b.status)),
+            ac_online: batteries.iter().any(|b| b.ac_online),
+            cycle_count: summed.iter().filter_map(|b| b.cycle_count).max(),
+            source: match summed.first() {
+                Some(first) if summed.iter().all(|b| b.source == first.source) => first.source,
+                // Both in µWh
+                _ => EnergySource::Charge,
+            },
+        }
+    }
+
+    /// Remaining energy relative to the last full charge
+    pub fn remaining(&self) -> Percent {
+        Percent::from_normalized((self.now as f64 / self.full as f64).clamp(0.0, 1.0))
+    }
+
+    /// Capacity lost compared to the design capacity
+    pub fn wear(&self) -> Percent {
+        Percent::from(self.full as f64, self.full_design as f64).inverse()
+    }
+}
+
+fn list_power_supplies_in(dir: &Path) -> Vec<PowerSupply> {
+    let mut supplies: Vec<PowerSupply> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| {
+                let path = entry.path();
+                let kind = fs::read_to_string(path.join("type"))
+                    .map(|content| SupplyType::parse(&content))
+                    .unwrap_or(SupplyType::Other);
+                PowerSupply {
+                    name: entry.file_name().to_string_lossy().into_owned(),
+                    kind,
+                    path,
+                }
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    supplies.sort_by(|a, b| a.name.cmp(&b.name));
+    supplies
+}
+
+/// List everything under `/sys/class/power_supply`, sorted by name
+pub fn list_power_supplies() -> Vec<PowerSupply> {
+    list_power_supplies_in(Path::new(POWER_SUPPLY_DIR))
+}
+
+fn read_battery(supply: &PowerSupply, on_ac: bool) -> Option<BatteryInfo> {
+    // Empty bays on dual-battery laptops still have a directory
+    if supply.read("present").as_deref() == Some("0") {
+        return None;
+    }
+    let mut info = read_energy(supply).or_else(|| read_charge(supply))?;
+    info.name = supply.name.clone();
+    info.ac_online = on_ac;
+    // Many drivers report 0 when they don't count cycles
+    info.cycle_count = supply.read_unsigned("cycle_count").filter(|&c| c > 0);
+    info.status = match supply.read("status") {
+        Some(status) => ChargeStatus::parse(&status),
+        None if on_ac => ChargeStatus::Charging,
+        None => ChargeStatus::Discharging,
+    };
+    Some(info)
+}
+
+fn read_energy(supply: &PowerSupply) -> Option<BatteryInfo> {
+    Some(BatteryInfo {
+        now: supply.read_unsigned("energy_now")?,
+        full: supply.read_unsigned("energy_full")?,
+        full_design: supply.read_unsigned("energy_full_design")?,
+        rate: supply.read_magnitude("power_now"),
+        source: EnergySource::Energy,
+        ..BatteryInfo::default()
+    })
+}
+
+fn read_charge(supply: &PowerSupply) -> Option<BatteryInfo> {
+    let now = supply.read_unsigned("charge_now")?;
+    let full = supply.read_unsigned("charge_full")?;
+    let full_design = supply.read_unsigned("charge_full_design")?;
+    // The voltage follows the load and the charge level, converting with it
+    // would make the capacity, and the wear computed from it, jump around
+    let voltage = supply
+        .read_unsigned("voltage_min_design")
+        .filter(|&v| v > 0)
+        // Some firmwares report 0 for voltage_now while discharging
+        .or_else(|| supply.read_unsigned("voltage_now"))
+        .filter(|&v| v > 0);
+    let current = supply.read_magnitude("current_now");
+    Some(match voltage {
+        Some(voltage) => BatteryInfo {
+            now: charge_to_energy(now, voltage),
+            full: charge_to_energy(full, voltage),
+            full_design: charge_to_energy(full_design, voltage),
+            rate: supply
+                .read_magnitude("power_now")
+                .or_else(|| current.map(|current| charge_to_energy(current, voltage))),
+            source: EnergySource::Charge,
+            ..BatteryInfo::default()
+        },
+        None => BatteryInfo {
+            now,
+            full,
+            full_design,
+            rate: current,
+            source: EnergySource::ChargeWithoutVoltage,
+            ..BatteryInfo::default()
+        },
+    })
+}
+
+/// µAh * µV = 10^-12 Wh, so we scale it back to µWh. Also works for µA to µW.
+fn charge_to_energy(charge: u64, voltage: u64) -> u64 {
+    (charge as u128 * voltage as u128 / 1_000_000) as u64
+}
+
+fn get_batteries_in(dir: &Path) -> Vec<BatteryInfo> {
+    let supplies = list_power_supplies_in(dir);
+    let on_ac = supplies.iter().any(|s| s.online());
+    supplies
+        .iter()
+        .filter(|s| s.kind == SupplyType::Battery)
+        .filter_map(|s| read_battery(s, on_ac))
+        .collect()
+}
+
+/// Read every battery present, sorted by name
+pub fn get_batteries() -> Vec<BatteryInfo> {
+    get_batteries_in(Path::new(POWER_SUPPLY_DIR))
+}
+
+/// All batteries combined into one, `None` if the machine has no battery
+pub fn get_battery_info() -> Option<BatteryInfo> {
+    let batteries = get_batteries();
+    if batteries.is_empty() {
+        None
+    } else {
+        Some(BatteryInfo::aggregate(&batteries))
+    }
+}
+
+/// Weight of the newest sample in the smoothed rate
+const RATE_SMOOTHING: f64 = 0.3;
+
+/// Estimate remaining time from successive battery readings.
+///
+/// The rate reported by the driver is used when available, otherwise it is
+/// derived from how much energy changed between two calls to [`update`](Self::update).
+#[derive(Debug, Default, Clone)]
+pub struct BatteryEstimator {
+    last_sample: Option<(Instant, u64, ChargeStatus)>,
+    /// Smoothed rate in µW (or µA), always positive
+    smoothed_rate: Option<f64>,
+}
+
+impl BatteryEstimator {
+    pub const fn new() -> Self {
+        BatteryEstimator {
+            last_sample: None,
+            smoothed_rate: None,
+        }
+    }
+
+    /// Feed a new reading, should be called regularly, e.g. once a minute
+    pub fn update(&mut self, info: &BatteryInfo) {
+        self.update_at(info, Instant::now());
+    }
+
+    fn update_at(&mut self, info: &BatteryInfo, at: Instant) {
+        match self.last_sample {
+            // Charging and discharging rates are unrelated, start over
+            Some((_, _, status)) if status != info.status => self.smoothed_rate = None,
+            Some((last_time, last_energy, _)) if info.now != last_energy => {
+                let hours = at.duration_since(last_time).as_secs_f64() / 3600.0;
+                if hours > 0.0 {
+                    let rate = (info.now as f64 - last_energy as f64).abs() / hours;
+                    self.smoothed_rate = Some(match self.smoothed_rate {
+                        Some(old) => old + RATE_SMOOTHING * (rate - old),
+                        None => rate,
+                    });
+                }
+            }
+            // The gauge didn't move yet, keep the old sample so the next
+            // difference spans a longer interval
+            Some(_) => return,
+            None => {}
+        }
+        self.last_sample = Some((at, info.now, info.status));
+    }
+
+    fn rate(&self, info: &BatteryInfo) -> Option<f64> {
+        info.rate
+            .filter(|&rate| rate > 0)
+            .map(|rate| rate as f64)
+            .or(self.smoothed_rate)
+            .filter(|&rate| rate > 0.0)
+    }
+
+    /// `None` when not discharging or when the rate is still unknown
+    pub fn time_to_empty(&self, info: &BatteryInfo) -> Option<Duration> {
+        if info.status != ChargeStatus::Discharging {
+            return None;
+        }
+        let hours = info.now as f64 / self.rate(info)?;
+        Some(Duration::from_secs_f64(hours * 3600.0))
+    }
+
+    /// `None` when not charging or when the rate is still unknown
+    pub fn time_to_full(&self, info: &BatteryInfo) -> Option<Duration> {
+        if info.status != ChargeStatus::Charging {
+            return None;
+        }
+        let hours = info.full.saturating_sub(info.now) as f64 / self.rate(info)?;
+        Some(Duration::from_secs_f64(hours * 3600.0))
+    }
+}
+
+/// How worried we should be about the remaining charge
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BatteryLevel {
+    Normal,
+    Low,
+    Critical,
+}
+
+/// Decide when to alert about low battery, at most once per level in each
+/// discharge cycle. A cycle ends when the charger is plugged in, a status
+/// flickering to `Unknown` on the way down doesn't end it.
+#[derive(Debug, Clone)]
+pub struct BatteryAlarm {
+    low: Percent,
+    critical: Percent,
+    /// The most severe level already reported since the charger was unplugged
+    reported: BatteryLevel,
+}
+
+impl BatteryAlarm {
+    /// Thresholds are in the range 0..=1, `critical` should be below `low`
+    pub fn new(low: Percent, critical: Percent) -> Self {
+        BatteryAlarm {
+            low,
+            critical,
+            reported: BatteryLevel::Normal,
+        }
+    }
+
+    pub fn level_of(&self, info: &BatteryInfo) -> BatteryLevel {
+        let remaining = info.remaining().value;
+        if remaining <= self.critical.value {
+            BatteryLevel::Critical
+        } else if remaining <= self.low.value {
+            BatteryLevel::Low
+        } else {
+            BatteryLevel::Normal
+        }
+    }
+
+    /// Return the level to alert for, `None` if it was already reported or
+    /// there is nothing to report
+    pub fn check(&mut self, info: &BatteryInfo) -> Option<BatteryLevel> {
+        let plugged_in = match info.status {
+            // Even with an adapter online, e.g. a weak one that can't keep up
+            ChargeStatus::Discharging => false,
+            ChargeStatus::Charging | ChargeStatus::Full => true,
+            // Either a glitch or the charge threshold was reached
+            ChargeStatus::NotCharging | ChargeStatus::Unknown => info.ac_online,
+        };
+        if plugged_in {
+            self.reported = BatteryLevel::Normal;
+            return None;
+        }
+        if info.status != ChargeStatus::Discharging {
+            return None;
+        }
+        let level = self.level_of(info);
+        if level > self.reported {
+            self.reported = level;
+            Some(level)
+        } else {
+            None
+        }
+    }
+}
+
+const START_THRESHOLD: &str = "charge_control_start_threshold";
+const END_THRESHOLD: &str = "charge_control_end_threshold";
+
+#[derive(thiserror::Error, Debug)]
+pub enum ThresholdError {
+    #[error("Battery {0} not found")]
+    BatteryNotFound(String),
+    #[error("Battery {0} doesn't support charge thresholds")]
+    Unsupported(String),
+    #[error("Battery {0} only supports the end threshold")]
+    StartUnsupported(String),
+    #[error("Invalid thresholds {start}-{end}, start must be below end and end at most 100")]
+    InvalidRange { start: u8, end: u8 },
+    #[error("Permission denied to access {0}")]
+    PermissionDenied(PathBuf),
+    #[error("Malformed content in {0}")]
+    Malformed(PathBuf),
+    #[error("IO error on {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+}
+
+/// Charging starts when the level drops below `start` and stops at `end`, both in percent
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ChargeThresholds {
+    /// Some drivers only support the end threshold
+    pub start: Option<u8>,
+    pub end: u8,
+}
+
+impl ChargeThresholds {
+    pub fn validate(&self) -> Result<(), ThresholdError> {
+        let start = self.start.unwrap_or(0);
+        if self.end > 100 || start >= self.end {
+            return Err(ThresholdError::InvalidRange {
+                start,
+                end: self.end,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl PowerSupply {
+    fn read_threshold(&self, file: &str) -> Result<Option<u8>, ThresholdError> {
+        let path = self.path.join(file);
+        match fs::read_to_string(&path) {
+            Ok(content) => content
+                .trim()
+                .parse()
+                .map(Some)
+                .map_err(|_| ThresholdError::Malformed(path)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+                Err(ThresholdError::PermissionDenied(path))
+            }
+            Err(e) => Err(ThresholdError::Io(path, e)),
+        }
+    }
+
+    fn write_threshold(&self, file: &str, value: u8) -> Result<(), ThresholdError> {
+        let path = self.path.join(file);
+        fs::write(&path, value.to_string()).map_err(|e| match e.kind() {
+            ErrorKind::PermissionDenied => ThresholdError::PermissionDenied(path.clone()),
+            _ => ThresholdError::Io(path.clone(), e),
+        })
+    }
+
+    pub fn charge_thresholds(&self) -> Result<ChargeThresholds, ThresholdError> {
+        match self.read_threshold(END_THRESHOLD)? {
+            Some(end) => Ok(ChargeThresholds {
+                start: self.read_threshold(START_THRESHOLD)?,
+                end,
+            }),
+            None => Err(ThresholdError::Unsupported(self.name.clone())),
+        }
+    }
+
+    pub fn set_charge_thresholds(
+        &self,
+        thresholds: ChargeThresholds,
+    ) -> Result<(), ThresholdError> {
+        thresholds.validate()?;
+        let current = self.charge_thresholds()?;
+        match (thresholds.start, current.start) {
+            (Some(_), None) => Err(ThresholdError::StartUnsupported(self.name.clone())),
+            (Some(start), Some(_)) => {
+                // The kernel rejects a write making start >= end, even temporarily,
+                // so the order depends on which direction the range moves
+                if start >= current.end {
+                    self.write_threshold(END_THRESHOLD, thresholds.end)?;
+                    self.write_threshold(START_THRESHOLD, start)
+                } else {
+                    self.write_threshold(START_THRESHOLD, start)?;
+                    self.write_threshold(END_THRESHOLD, thresholds.end)
+                }
+            }
+            // The start threshold stays, the end must remain above it
+            (None, Some(start)) if thresholds.end <= start => Err(ThresholdError::InvalidRange {
+                start,
+                end: thresholds.end,
+            }),
+            (None, _) => self.write_threshold(END_THRESHOLD, thresholds.end),
+        }
+    }
+}
+
+fn find_battery_in(dir: &Path, name: &str) -> Result<PowerSupply, ThresholdError> {
+    list_power_supplies_in(dir)
+        .into_iter()
+        .find(|s| s.kind == SupplyType::Battery && s.name == name)
+        .ok_or_else(|| ThresholdError::BatteryNotFound(name.to_string()))
+}
+
+/// Find a battery by name, e.g. `BAT0`
+pub fn find_battery(name: &str) -> Result<PowerSupply, ThresholdError> {
+    find_battery_in(Path::new(POWER_SUPPLY_DIR), name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fake_supply(root: &Path, name: &str, files: &[(&str, &str)]) {
+        let dir = root.join(name);
+        fs::create_dir_all(&dir).unwrap();
+        for (file, content) in files {
+            fs::write(dir.join(file), format!("{}\n", content)).unwrap();
+        }
+    }
+
+    #[test]
+    fn supply_type_is_parsed_from_type_file() {
+        assert_eq!(SupplyType::parse("Battery\n"), SupplyType::Battery);
+        assert_eq!(SupplyType::parse("Mains"), SupplyType::Mains);
+        assert_eq!(SupplyType::parse("USB"), SupplyType::Usb);
+        assert_eq!(SupplyType::parse("USB_PD"), SupplyType::Usb);
+        assert_eq!(SupplyType::parse("UPS"), SupplyType::Other);
+    }
+
+    #[test]
+    fn charge_status_is_parsed_from_status_file() {
+        assert_eq!(ChargeStatus::parse("Charging\n"), ChargeStatus::Charging);
+        assert_eq!(
+            ChargeStatus::parse("Not charging"),
+            ChargeStatus::NotCharging
+        );
+        assert_eq!(ChargeStatus::parse("Full"), ChargeStatus::Full);
+        assert_eq!(ChargeStatus::parse("???"), ChargeStatus::Unknown);
+    }
+
+    #[test]
+    fn aggregate_prefers_active_status() {
+        use ChargeStatus::*;
+        assert_eq!(
+            ChargeStatus::combine([Full, Charging].into_iter()),
+            Charging
+        );
+        assert_eq!(
+            ChargeStatus::combine([Unknown, Discharging].into_iter()),
+            Discharging
+        );
+        assert_eq!(ChargeStatus::combine([Full, Full].into_iter()), Full);
+        assert_eq!(ChargeStatus::combine([].into_iter()), Unknown);
+    }
+
+    #[test]
+    fn aggregate_sums_energy_of_all_packs() {
+        let a = BatteryInfo {
+            name: "BAT0".to_string(),
+            now: 10,
+            full: 20,
+            full_design: 30,
+            rate: None,
+            status: ChargeStatus::Discharging,
+            ac_online: false,
+            cycle_count: None,
+            source: EnergySource::Energy,
+        };
+        let b = BatteryInfo {
+            name: "BAT1".to_string(),
+            now: 5,
+            full: 10,
+            full_design: 10,
+            rate: None,
+            status: ChargeStatus::Discharging,
+            ac_online: false,
+            cycle_count: None,
+            source: EnergySource::Energy,
+        };
+        let total = BatteryInfo::aggregate(&[a.clone(), b]);
+        assert_eq!(total.name, "BAT0+BAT1");
+        assert_eq!((total.now, total.full, total.full_design), (15, 30, 40));
+
+        let without_voltage = BatteryInfo {
+            name: "BAT2".to_string(),
+            now: 3000,
+            full: 4000,
+            full_design: 5000,
+            status: ChargeStatus::Full,
+            source: EnergySource::ChargeWithoutVoltage,
+            ..BatteryInfo::default()
+        };
+        let total = BatteryInfo::aggregate(&[a, without_voltage.clone()]);
+        assert_eq!(total.name, "BAT0");
+        assert_eq!((total.now, total.full, total.full_design), (10, 20, 30));
+        assert_eq!(total.source, EnergySource::Energy);
+        assert_eq!(total.status, ChargeStatus::Discharging);
+        let alone = BatteryInfo::aggregate(&[without_voltage]);
+        assert_eq!(alone.now, 3000);
+        assert_eq!(alone.source, EnergySource::ChargeWithoutVoltage);
+    }
+
+    #[test]
+    fn batteries_are_discovered_regardless_of_names() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let energy = [
+            ("type", "Battery"),
+            ("energy_now", "1"),
+            ("energy_full", "2"),
+            ("energy_full_design", "3"),
+        ];
+        fake_supply(root, "BAT1", &energy);
+        fake_supply(root, "BAT0", &energy);
+        fake_supply(root, "BAT2", &[("type", "Battery"), ("present", "0")]);
+        fake_supply(root, "ADP1", &[("type", "Mains"), ("online", "1")]);
+
+        let batteries = get_batteries_in(root);
+        let names: Vec<&str> = batteries.iter().map(|b| b.name.as_str()).collect();
+        assert_eq!(names, ["BAT0", "BAT1"]);
+        assert!(batteries.iter().all(|b| b.ac_online));
+        assert!(batteries.iter().all(|b| b.status == ChargeStatus::Charging));
+    }
+
+    #[test]
+    fn charge_is_converted_to_energy_with_voltage() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fake_supply(
+            root,
+            "BAT0",
+            &[
+                ("type", "Battery"),
+                ("charge_now", "2000000"),
+                ("charge_full", "4000000"),
+                ("charge_full_design", "5000000"),
+                ("voltage_now", "12000000"),
+            ],
+        );
+
+        let battery = &get_batteries_in(root)[0];
+        assert_eq!(battery.source, EnergySource::Charge);
+        // 2 Ah at 12 V is 24 Wh
+        assert_eq!(battery.now, 24_000_000);
+        assert_eq!(battery.full, 48_000_000);
+        assert_eq!(battery.full_design, 60_000_000);
+
+        // The design voltage is stable, unlike the current one
+        fake_supply(
+            root,
+            "BAT0",
+            &[
+                ("voltage_min_design", "11000000"),
+                ("voltage_now", "12600000"),
+                ("current_now", "1000000"),
+            ],
+        );
+        let battery = &get_batteries_in(root)[0];
+        assert_eq!(battery.full, 44_000_000);
+        assert_eq!(battery.rate, Some(11_000_000));
+    }
+
+    #[test]
+    fn estimator_prefers_rate_reported_by_driver() {
+        let info = BatteryInfo {
+            now: 20_000_000,
+            full: 40_000_000,
+            rate: Some(10_000_000),
+            status: ChargeStatus::Discharging,
+            ..BatteryInfo::default()
+        };
+        let estimator = BatteryEstimator::new();
+        assert_eq!(
+            estimator.time_to_empty(&info),
+            Some(Duration::from_secs(2 * 3600))
+        );
+        assert_eq!(estimator.time_to_full(&info), None);
+    }
+
+    #[test]
+    fn estimator_falls_back_to_sampled_rate() {
+        let mut info = BatteryInfo {
+            now: 20_000_000,
+            full: 40_000_000,
+            status: ChargeStatus::Discharging,
+            ..BatteryInfo::default()
+        };
+        let mut estimator = BatteryEstimator::new();
+        let start = Instant::now();
+        estimator.update_at(&info, start);
+        assert_eq!(estimator.time_to_empty(&info), None);
+
+        // 1 Wh in 6 minutes is 10 W
+        info.now = 19_000_000;
+        estimator.update_at(&info, start + Duration::from_secs(360));
+        let remaining = estimator.time_to_empty(&info).unwrap().as_secs_f64();
+        assert!((remaining - 1.9 * 3600.0).abs() < 1.0);
+
+        info.status = ChargeStatus::Charging;
+        estimator.update_at(&info, start + Duration::from_secs(420));
+        assert_eq!(estimator.time_to_full(&info), None);
+    }
+
+    #[test]
+    fn alarm_fires_once_per_level_and_resets_when_plugged_in() {
+        let mut alarm = BatteryAlarm::new(
+            Percent::from_normalized(0.15),
+            Percent::from_normalized(0.05),
+        );
+        let mut info = BatteryInfo {
+            now: 50,
+            full: 100,
+            status: ChargeStatus::Discharging,
+            ..BatteryInfo::default()
+        };
+        assert_eq!(alarm.check(&info), None);
+        info.now = 15;
+        assert_eq!(alarm.check(&info), Some(BatteryLevel::Low));
+        info.now = 10;
+        assert_eq!(alarm.check(&info), None);
+        info.now = 4;
+        assert_eq!(alarm.check(&info), Some(BatteryLevel::Critical));
+        assert_eq!(alarm.check(&info), None);
+
+        // A glitch in the status doesn't start a new cycle
+        info.status = ChargeStatus::Unknown;
+        assert_eq!(alarm.check(&info), None);
+        info.status = ChargeStatus::NotCharging;
+        assert_eq!(alarm.check(&info), None);
+        info.status = ChargeStatus::Discharging;
+        assert_eq!(alarm.check(&info), None);
+
+        info.status = ChargeStatus::Charging;
+        assert_eq!(alarm.check(&info), None);
+        info.status = ChargeStatus::Discharging;
+        assert_eq!(alarm.check(&info), Some(BatteryLevel::Critical));
+    }
+
+    #[test]
+    fn thresholds_must_be_ordered_and_at_most_100() {
+        let valid = ChargeThresholds {
+            start: Some(75),
+            end: 80,
+        };
+        assert!(valid.validate().is_ok());
+        let reversed = ChargeThresholds {
+            start: Some(80),
+            end: 75,
+        };
+        assert!(matches!(
+            reversed.validate(),
+            Err(ThresholdError::InvalidRange { start: 80, end: 75 })
+        ));
+        let too_high = ChargeThresholds {
+            start: None,
+            end: 101,
+        };
+        assert!(too_high.validate().is_err());
+    }
+
+    #[test]
+    fn thresholds_are_read_and_written() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fake_supply(
+            root,
+            "BAT0",
+            &[
+                ("type", "Battery"),
+                (START_THRESHOLD, "0"),
+                (END_THRESHOLD, "100"),
+            ],
+        );
+        fake_supply(root, "BAT1", &[("type", "Battery")]);
+
+        let battery = find_battery_in(root, "BAT0").unwrap();
+        let thresholds = ChargeThresholds {
+            start: Some(75),
+            end: 80,
+        };
+        battery.set_charge_thresholds(thresholds).unwrap();
+        assert_eq!(battery.charge_thresholds().unwrap(), thresholds);
+        // Only the end, below the current start
+        let end_only = ChargeThresholds {
+            start: None,
+            end: 70,
+        };
+        assert!(matches!(
+            battery.set_charge_thresholds(end_only),
+            Err(ThresholdError::InvalidRange { start: 75, end: 70 })
+        ));
+        assert_eq!(battery.charge_thresholds().unwrap(), thresholds);
+        let end_only = ChargeThresholds {
+            start: None,
+            end: 90,
+        };
+        battery.set_charge_thresholds(end_only).unwrap();
+        assert_eq!(battery.charge_thresholds().unwrap().end, 90);
+
+        let unsupported = find_battery_in(root, "BAT1").unwrap();
+        assert!(matches!(
+            unsupported.charge_thresholds(),
+            Err(ThresholdError::Unsupported(_))
+        ));
+        assert!(matches!(
+            find_battery_in(root, "BAT2"),
+            Err(ThresholdError::BatteryNotFound(_))
+        ));
     }
 }
@@ -0,0 +1,340 @@
+use std::fmt::{self, Display, Formatter};
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::battery::BatteryInfo;
+
+const HISTORY_FILE: &str = "battery-health.tsv";
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+#[derive(thiserror::Error, Debug)]
+pub enum HealthError {
+    #[error("Neither XDG_STATE_HOME nor HOME is set")]
+    NoStateDir,
+    #[error("IO error on {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+}
+
+/// A calendar date, stored as days since 1970-01-01
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    pub days: i64,
+}
+
+impl Date {
+    /// Today in the local time zone, or in UTC if it can't be determined
+    pub fn today() -> Self {
+        let since_epoch = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("System clock is before 1970");
+        let now = since_epoch.as_secs() as libc::time_t;
+        // SAFETY: tm is plain old data, and localtime_r only writes into it
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        if unsafe { libc::localtime_r(&now, &mut tm) }.is_null() {
+            return Date {
+                days: (since_epoch.as_secs() / SECONDS_PER_DAY) as i64,
+            };
+        }
+        Date::from_ymd(
+            tm.tm_year as i64 + 1900,
+            tm.tm_mon as u32 + 1,
+            tm.tm_mday as u32,
+        )
+    }
+
+    /// Convert to (year, month, day), see http://howardhinnant.github.io/date_algorithms.html
+    pub fn to_ymd(self) -> (i64, u32, u32) {
+        let z = self.days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+        (year, month, day)
+    }
+
+    pub fn from_ymd(year: i64, month: u32, day: u32) -> Self {
+        let year = if month <= 2 { year - 1 } else { year };
+        let era = year.div_euclid(400);
+        let yoe = year.rem_euclid(400);
+        let month = month as i64;
+        let doy = (153 * if month > 2 { month - 3 } else { month + 9 } + 2) / 5 + day as i64 - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        Date {
+            days: era * 146097 + doe - 719468,
+        }
+    }
+
+    /// Parse `YYYY-MM-DD`
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.splitn(3, '-');
+        let year = parts.next()?.parse().ok()?;
+        let month = parts
+            .next()?
+            .parse()
+            .ok()
+            .filter(|m| (1..=12).contains(m))?;
+        let day = parts
+            .next()?
+            .parse()
+            .ok()
+            .filter(|d| (1..=31).contains(d))?;
+        Some(Date::from_ymd(year, month, day))
+    }
+}
+
+impl Display for Date {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let (year, month, day) = self.to_ymd();
+        write!(f, "{:04}-{:02}-{:02}", year, month, day)
+    }
+}
+
+/// One line of the history file
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct HealthSample {
+    pub date: Date,
+    pub energy_full: u64,
+    pub energy_full_design: u64,
+    pub cycle_count: Option<u64>,
+}
+
+impl HealthSample {
+    pub fn from_battery(date: Date, info: &BatteryInfo) -> Self {
+        HealthSample {
+            date,
+            energy_full: info.full,
+            energy_full_design: info.full_design,
+            cycle_count: info.cycle_count,
+        }
+    }
+
+    /// Remaining capacity compared to the design capacity, in percent
+    pub fn capacity(&self) -> f64 {
+        self.energy_full as f64 / self.energy_full_design as f64 * 100.0
+    }
+
+    pub fn wear(&self) -> f64 {
+        100.0 - self.capacity()
+    }
+
+    /// Tab separated: date, energy_full, energy_full_design, cycle_count (`-` if unknown), wear
+    fn to_line(self) -> String {
+        let cycle_count = self
+            .cycle_count
+            .map_or_else(|| "-".to_string(), |c| c.to_string());
+        format!(
+            "{}\t{}\t{}\t{}\t{:.2}",
+            self.date,
+            self.energy_full,
+            self.energy_full_design,
+            cycle_count,
+            self.wear()
+        )
+    }
+
+    /// The wear column is only for humans reading the file, it's derived again when needed
+    fn parse(line: &str) -> Option<Self> {
+        let parts: Vec<&str> = line.split('\t').collect();
+        if parts.len() < 4 {
+            return None;
+        }
+        Some(HealthSample {
+            date: Date::parse(parts[0])?,
+            energy_full: parts[1].parse().ok()?,
+            energy_full_design: parts[2].parse().ok().filter(|&d| d > 0)?,
+            cycle_count: match parts[3] {
+                "-" => None,
+                count => Some(count.parse().ok()?),
+            },
+        })
+    }
+}
+
+/// `$XDG_STATE_HOME/obutils`, defaulting to `~/.local/state/obutils`
+pub fn state_dir() -> Result<PathBuf, HealthError> {
+    let base = match std::env::var_os("XDG_STATE_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME").ok_or(HealthError::NoStateDir)?)
+            .join(".local/state"),
+    };
+    Ok(base.join("obutils"))
+}
+
+pub fn history_path() -> Result<PathBuf, HealthError> {
+    Ok(state_dir()?.join(HISTORY_FILE))
+}
+
+/// Samples of the history file, oldest first
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct History {
+    pub samples: Vec<HealthSample>,
+    /// Numbers of the lines that couldn't be parsed, they are skipped
+    pub malformed: Vec<usize>,
+}
+
+/// Read all samples. A missing file is an empty history.
+pub fn read_history(path: &Path) -> Result<History, HealthError> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(History::default()),
+        Err(e) => return Err(HealthError::Io(path.to_path_buf(), e)),
+    };
+    let mut history = History::default();
+    for (index, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match HealthSample::parse(line) {
+            Some(sample) => history.samples.push(sample),
+            None => history.malformed.push(index + 1),
+        }
+    }
+    Ok(history)
+}
+
+/// Append a sample unless the history already has one for the same day.
+/// Return whether the sample was written.
+pub fn record_sample(path: &Path, sample: HealthSample) -> Result<bool, HealthError> {
+    let io_error = |e| HealthError::Io(path.to_path_buf(), e);
+    if read_history(path)?
+        .samples
+        .last()
+        .is_some_and(|last| last.date >= sample.date)
+    {
+        return Ok(false);
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(io_error)?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(io_error)?;
+    writeln!(file, "{}", sample.to_line()).map_err(io_error)?;
+    Ok(true)
+}
+
+/// Record today's sample of a battery into the default history file
+pub fn record(info: &BatteryInfo) -> Result<bool, HealthError> {
+    record_sample(
+        &history_path()?,
+        HealthSample::from_battery(Date::today(), info),
+    )
+}
+
+/// Fit a line through the capacity history and find when it crosses
+/// `capacity` percent. `None` if there isn't enough data or capacity isn't
+/// going down.
+pub fn project_capacity_date(history: &[HealthSample], capacity: f64) -> Option<Date> {
+    if history.len() < 2 {
+        return None;
+    }
+    let n = history.len() as f64;
+    let mean_x = history.iter().map(|s| s.date.days as f64).sum::<f64>() / n;
+    let mean_y = history.iter().map(|s| s.capacity()).sum::<f64>() / n;
+    let (covariance, variance) = history.iter().fold((0.0, 0.0), |(cov, var), s| {
+        let dx = s.date.days as f64 - mean_x;
+        (cov + dx * (s.capacity() - mean_y), var + dx * dx)
+    });
+    if variance == 0.0 {
+        return None;
+    }
+    let slope = covariance / variance;
+    if slope >= 0.0 {
+        return None;
+    }
+    let days = mean_x + (capacity - mean_y) / slope;
+    Some(Date {
+        days: days.round() as i64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(date: &str, energy_full: u64) -> HealthSample {
+        HealthSample {
+            date: Date::parse(date).unwrap(),
+            energy_full,
+            energy_full_design: 100,
+            cycle_count: None,
+        }
+    }
+
+    #[test]
+    fn date_round_trips_through_text() {
+        for text in ["1970-01-01", "2000-02-29", "2026-10-18", "2100-12-31"] {
+            assert_eq!(Date::parse(text).unwrap().to_string(), text);
+        }
+        assert_eq!(Date::parse("1970-01-02").unwrap().days, 1);
+        assert_eq!(Date::parse("2026-13-01"), None);
+    }
+
+    #[test]
+    fn sample_round_trips_through_line() {
+        let mut original = sample("2026-10-18", 80);
+        original.cycle_count = Some(123);
+        assert_eq!(original.to_line(), "2026-10-18\t80\t100\t123\t20.00");
+        assert_eq!(HealthSample::parse(&original.to_line()), Some(original));
+
+        let no_cycles = sample("2026-10-18", 80);
+        assert_eq!(HealthSample::parse(&no_cycles.to_line()), Some(no_cycles));
+    }
+
+    #[test]
+    fn projection_follows_linear_trend() {
+        let history = [sample("2026-01-01", 90), sample("2026-01-11", 89)];
+        // Losing 1% every 10 days, 80% is 100 days after the first sample
+        let date = project_capacity_date(&history, 80.0).unwrap();
+        assert_eq!(date.to_string(), "2026-04-11");
+    }
+
+    #[test]
+    fn projection_needs_decreasing_capacity() {
+        assert_eq!(
+            project_capacity_date(&[sample("2026-01-01", 90)], 80.0),
+            None
+        );
+        let flat = [sample("2026-01-01", 90), sample("2026-01-11", 90)];
+        assert_eq!(project_capacity_date(&flat, 80.0), None);
+    }
+
+    #[test]
+    fn only_one_sample_is_recorded_per_day() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join(HISTORY_FILE);
+        assert!(record_sample(&path, sample("2026-01-01", 90)).unwrap());
+        assert!(!record_sample(&path, sample("2026-01-01", 89)).unwrap());
+        assert!(record_sample(&path, sample("2026-01-02", 89)).unwrap());
+        assert_eq!(
+            read_history(&path).unwrap().samples,
+            [sample("2026-01-01", 90), sample("2026-01-02", 89)]
+        );
+    }
+
+    #[test]
+    fn malformed_lines_are_skipped() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join(HISTORY_FILE);
+        let content = format!(
+            "{}\ngarbage\n\n{}\n",
+            sample("2026-01-01", 90).to_line(),
+            sample("2026-01-02", 89).to_line()
+        );
+        fs::write(&path, content).unwrap();
+
+        let history = read_history(&path).unwrap();
+        assert_eq!(history.samples.len(), 2);
+        assert_eq!(history.malformed, [2]);
+        assert!(!record_sample(&path, sample("2026-01-02", 88)).unwrap());
+        assert!(record_sample(&path, sample("2026-01-03", 88)).unwrap());
+    }
+}
@@ -0,0 +1,97 @@
+use obutils::ambient_light::{AutoBrightness, LightSensor, LuxCurve};
+use obutils::brightness::{Backlight, BrightnessCurve, BrightnessError};
+use std::thread::sleep;
+use std::time::Duration;
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+const FADE_DURATION: Duration = Duration::from_millis(500);
+
+#[derive(Debug)]
+struct Arguments {
+    /// Name of the backlight under `/sys/class/backlight`, auto-detected if `None`
+    pub backlight: Option<String>,
+    /// How brightness percentages map to raw backlight values
+    pub curve: BrightnessCurve,
+    /// Brightness percent for each illuminance
+    pub points: LuxCurve,
+    /// Minimum change in percent before touching the backlight
+    pub hysteresis: u8,
+}
+
+fn print_usage_and_exit(exe_name: &str) -> ! {
+    eprintln!(
+        "Usage: {} [--backlight name] [--curve linear|exponential|cie] [--points lux:percent,...] [--hysteresis percent]",
+        exe_name
+    );
+    std::process::exit(1);
+}
+
+fn parse_args() -> Arguments {
+    let mut args = std::env::args();
+    let exe_name = args.next().unwrap();
+    let mut result = Arguments {
+        backlight: None,
+        curve: BrightnessCurve::Cie,
+        points: LuxCurve::default(),
+        hysteresis: 5,
+    };
+    while let Some(arg) = args.next() {
+        let value = args
+            .next()
+            .unwrap_or_else(|| print_usage_and_exit(&exe_name));
+        match arg.as_str() {
+            "--backlight" => result.backlight = Some(value),
+            "--curve" => {
+                result.curve = value
+                    .parse()
+                    .unwrap_or_else(|_| print_usage_and_exit(&exe_name))
+            }
+            "--points" => {
+                result.points = value.parse().unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    print_usage_and_exit(&exe_name)
+                })
+            }
+            "--hysteresis" => {
+                result.hysteresis = value
+                    .parse()
+                    .unwrap_or_else(|_| print_usage_and_exit(&exe_name))
+            }
+            _ => print_usage_and_exit(&exe_name),
+        }
+    }
+    result
+}
+
+/// Adjust the brightness to one illuminance sample
+fn follow(
+    sensor: &LightSensor,
+    backlight: &Backlight,
+    auto: &mut AutoBrightness,
+) -> Result<(), BrightnessError> {
+    // The sensor can disappear for a moment, e.g. when resuming from suspend
+    if let Some(lux) = sensor.read_lux() {
+        if let Some(target) = auto.update(lux, backlight.get_brightness()?) {
+            let faded = backlight.fade_to(target, FADE_DURATION);
+            // Even a failed fade may have changed the brightness
+            auto.applied(backlight.get_brightness()?);
+            faded?;
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    let args = parse_args();
+    let sensor = LightSensor::find().expect("Find an ambient light sensor");
+    let backlight = Backlight::find(args.backlight.as_deref())
+        .expect("Find a backlight")
+        .with_curve(args.curve);
+    let mut auto = AutoBrightness::new(args.points, args.hysteresis);
+    loop {
+        if let Err(e) = follow(&sensor, &backlight, &mut auto) {
+            eprintln!("{}", e);
+        }
+        sleep(SAMPLE_INTERVAL);
+    }
+}
@@ -0,0 +1,110 @@
+use obutils::battery::get_battery_info;
+use obutils::battery_health::{
+    history_path, project_capacity_date, read_history, record, HealthError, History,
+};
+
+/// Default capacity to project, in percent of the design capacity
+const DEFAULT_CAPACITY: f64 = 80.0;
+
+fn print_usage_and_exit(exe_name: &str) -> ! {
+    eprintln!("Usage: {} record", exe_name);
+    eprintln!(
+        "       {} report [capacity-percent, default {}]",
+        exe_name, DEFAULT_CAPACITY
+    );
+    std::process::exit(1);
+}
+
+fn report(capacity: f64) -> Result<(), HealthError> {
+    let path = history_path()?;
+    let History {
+        samples: history,
+        malformed,
+    } = read_history(&path)?;
+    if !malformed.is_empty() {
+        let lines: Vec<String> = malformed.iter().map(usize::to_string).collect();
+        eprintln!(
+            "Skipped malformed lines {} of {}",
+            lines.join(", "),
+            path.display()
+        );
+    }
+    if history.is_empty() {
+        println!("No battery health recorded yet");
+        return Ok(());
+    }
+    println!("Date        Capacity  Wear    Cycles");
+    for sample in &history {
+        let cycles = sample
+            .cycle_count
+            .map_or_else(|| "-".to_string(), |c| c.to_string());
+        println!(
+            "{}  {:6.1}%  {:5.1}%  {}",
+            sample.date,
+            sample.capacity(),
+            sample.wear(),
+            cycles
+        );
+    }
+    let first = history.first().unwrap();
+    let last = history.last().unwrap();
+    let days = last.date.days - first.date.days;
+    if days > 0 {
+        println!(
+            "Wear went from {:.1}% to {:.1}% in {} days",
+            first.wear(),
+            last.wear(),
+            days
+        );
+    }
+    match project_capacity_date(&history, capacity) {
+        Some(date) if last.capacity() <= capacity => {
+            println!(
+                "Capacity already dropped below {}% around {}",
+                capacity, date
+            )
+        }
+        Some(date) => println!(
+            "Capacity is projected to drop below {}% on {}",
+            capacity, date
+        ),
+        None => println!(
+            "Not enough data to project when capacity drops below {}%",
+            capacity
+        ),
+    }
+    Ok(())
+}
+
+fn run() -> Result<(), HealthError> {
+    let args: Vec<String> = std::env::args().collect();
+    let exe_name = &args[0];
+    let args: Vec<&str> = args[1..].iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["record"] => match get_battery_info() {
+            Some(info) => {
+                if !record(&info)? {
+                    eprintln!("Already recorded today");
+                }
+            }
+            None => eprintln!("No battery found"),
+        },
+        ["report"] => report(DEFAULT_CAPACITY)?,
+        ["report", capacity] => report(
+            capacity
+                .parse()
+                .ok()
+                .filter(|c| (0.0..=100.0).contains(c))
+                .unwrap_or_else(|| print_usage_and_exit(exe_name)),
+        )?,
+        _ => print_usage_and_exit(exe_name),
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    }
+}
@@ -0,0 +1,66 @@
+use obutils::battery::{
+    find_battery, list_power_supplies, ChargeThresholds, PowerSupply, SupplyType, ThresholdError,
+};
+
+fn print_usage_and_exit(exe_name: &str) -> ! {
+    eprintln!("Usage: {} get [battery]", exe_name);
+    eprintln!("       {} set battery [start] end", exe_name);
+    std::process::exit(1);
+}
+
+fn parse_percent(exe_name: &str, value: &str) -> u8 {
+    value
+        .parse::<u8>()
+        .unwrap_or_else(|_| print_usage_and_exit(exe_name))
+}
+
+/// Print one line per battery: `name start end`, `-` if the start threshold is unsupported
+fn print_thresholds(battery: &PowerSupply) -> Result<(), ThresholdError> {
+    let thresholds = battery.charge_thresholds()?;
+    let start = thresholds
+        .start
+        .map_or_else(|| "-".to_string(), |start| start.to_string());
+    println!("{} {} {}", battery.name, start, thresholds.end);
+    Ok(())
+}
+
+fn run() -> Result<(), ThresholdError> {
+    let args: Vec<String> = std::env::args().collect();
+    let exe_name = &args[0];
+    let args: Vec<&str> = args[1..].iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["get"] => {
+            let batteries: Vec<PowerSupply> = list_power_supplies()
+                .into_iter()
+                .filter(|s| s.kind == SupplyType::Battery)
+                .collect();
+            for battery in &batteries {
+                match print_thresholds(battery) {
+                    Err(ThresholdError::Unsupported(_)) => {}
+                    result => result?,
+                }
+            }
+        }
+        ["get", name] => print_thresholds(&find_battery(name)?)?,
+        ["set", name, end] => find_battery(name)?.set_charge_thresholds(ChargeThresholds {
+            start: None,
+            end: parse_percent(exe_name, end),
+        })?,
+        ["set", name, start, end] => {
+            find_battery(name)?.set_charge_thresholds(ChargeThresholds {
+                start: Some(parse_percent(exe_name, start)),
+                end: parse_percent(exe_name, end),
+            })?
+        }
+        _ => print_usage_and_exit(exe_name),
+    }
+    Ok(())
+}
+
+fn main() {
+    // Scripts check the exit code, humans read the message
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    }
+}
@@ -0,0 +1,86 @@
+use obutils::brightness::{Backlight, BrightnessControl, BrightnessCurve, BrightnessError};
+use obutils::ddc::DdcDisplay;
+use std::path::Path;
+use std::time::Duration;
+
+fn print_usage_and_exit(exe_name: &str) -> ! {
+    eprintln!("Usage: {} [options] get", exe_name);
+    eprintln!("       {} [options] set percent", exe_name);
+    eprintln!("       {} [options] up|down percent", exe_name);
+    eprintln!("       {} [options] fade percent duration-in-ms", exe_name);
+    eprintln!("Options: --backlight name");
+    eprintln!("         --curve linear|exponential|cie");
+    eprintln!("         --display i2c-N (external monitor through DDC/CI)");
+    std::process::exit(1);
+}
+
+fn parse<T: std::str::FromStr>(exe_name: &str, value: &str) -> T {
+    value
+        .parse()
+        .unwrap_or_else(|_| print_usage_and_exit(exe_name))
+}
+
+fn control(
+    exe_name: &str,
+    device: &dyn BrightnessControl,
+    args: &[&str],
+) -> Result<(), BrightnessError> {
+    match args {
+        ["get"] => println!("{}", device.get_brightness()?),
+        ["set", percent] => device.set_brightness(parse(exe_name, percent))?,
+        ["up", delta] => println!("{}", device.step_brightness(parse(exe_name, delta))?),
+        ["down", delta] => {
+            let delta: i8 = parse(exe_name, delta);
+            println!("{}", device.step_brightness(delta.saturating_neg())?)
+        }
+        _ => print_usage_and_exit(exe_name),
+    }
+    Ok(())
+}
+
+fn run() -> Result<(), BrightnessError> {
+    let args: Vec<String> = std::env::args().collect();
+    let exe_name = &args[0];
+    let mut args: &[String] = &args[1..];
+    let mut name = None;
+    let mut curve = BrightnessCurve::default();
+    let mut display = None;
+    loop {
+        match args {
+            [option, value, ..] if option == "--backlight" => name = Some(value.as_str()),
+            [option, value, ..] if option == "--curve" => curve = parse(exe_name, value),
+            [option, value, ..] if option == "--display" => display = Some(value.as_str()),
+            _ => break,
+        }
+        args = &args[2..];
+    }
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    if let Some(display) = display {
+        let display = DdcDisplay::open(&Path::new("/dev").join(display))?;
+        return match args.as_slice() {
+            // Monitors take tens of milliseconds per request, too slow to fade
+            ["fade", percent, _] => display.set_brightness(parse(exe_name, percent)),
+            _ => control(exe_name, &display, &args),
+        };
+    }
+    let backlight = Backlight::find(name)
+        .unwrap_or_else(|| {
+            eprintln!("No backlight found");
+            std::process::exit(2);
+        })
+        .with_curve(curve);
+    match args.as_slice() {
+        ["fade", percent, millis] => backlight.fade_to(
+            parse(exe_name, percent),
+            Duration::from_millis(parse(exe_name, millis)),
+        ),
+        _ => control(exe_name, &backlight, &args),
+    }
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    }
+}
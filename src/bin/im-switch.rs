@@ -0,0 +1,81 @@
+use obutils::fcitx::{
+    current_group_input_methods, cycle, find_input_method, step_input_method, Fcitx5ControllerProxy,
+};
+use std::process::exit;
+use zbus::Connection;
+
+#[derive(thiserror::Error, Debug)]
+enum Error {
+    #[error("Input method {0} not found")]
+    UnknownInputMethod(String),
+    #[error("The current group has no input method")]
+    EmptyGroup,
+    #[error("Zbus error: {0}")]
+    Zbus(#[from] zbus::Error),
+}
+
+fn print_usage_and_exit(exe_name: &str) -> ! {
+    eprintln!(
+        "Usage: {} next|prev|toggle|activate|deactivate|reload",
+        exe_name
+    );
+    eprintln!("       {} set name", exe_name);
+    eprintln!("       {} group [name]", exe_name);
+    eprintln!("       {} list", exe_name);
+    exit(1);
+}
+
+async fn run() -> Result<(), Error> {
+    let args: Vec<String> = std::env::args().collect();
+    let exe_name = &args[0];
+    let args: Vec<&str> = args[1..].iter().map(String::as_str).collect();
+    let connection = Connection::session().await?;
+    let proxy = Fcitx5ControllerProxy::new(&connection).await?;
+    match args.as_slice() {
+        ["next"] => {
+            step_input_method(&proxy, 1)
+                .await?
+                .ok_or(Error::EmptyGroup)?;
+        }
+        ["prev"] => {
+            step_input_method(&proxy, -1)
+                .await?
+                .ok_or(Error::EmptyGroup)?;
+        }
+        ["toggle"] => proxy.toggle().await?,
+        ["activate"] => proxy.activate().await?,
+        ["deactivate"] => proxy.deactivate().await?,
+        ["reload"] => proxy.reload().await?,
+        ["set", name] => {
+            let target = find_input_method(&proxy, name)
+                .await?
+                .ok_or_else(|| Error::UnknownInputMethod(name.to_string()))?;
+            proxy.set_current_input_method(&target).await?;
+        }
+        ["group"] => {
+            let groups = proxy.input_method_groups().await?;
+            let current = proxy.current_input_method_group().await?;
+            if let Some(group) = cycle(&groups, &current, 1) {
+                proxy.switch_input_method_group(group).await?;
+            }
+        }
+        ["group", name] => proxy.switch_input_method_group(name).await?,
+        ["list"] => {
+            let current = proxy.current_input_method().await?;
+            for name in current_group_input_methods(&proxy).await? {
+                let marker = if name == current { "*" } else { " " };
+                println!("{} {}", marker, name);
+            }
+        }
+        _ => print_usage_and_exit(exe_name),
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    if let Err(e) = run().await {
+        eprintln!("{}", e);
+        exit(2);
+    }
+}
@@ -1,15 +1,22 @@
-use obutils::fcitx::{Fcitx5ControllerProxy, InputMethod};
+use futures_util::StreamExt;
+use obutils::fcitx::{Fcitx5ControllerProxy, InputMethod, InputMethodSwitches};
 use obutils::keyboard_leds::{get_input_id, get_leds_state};
-use obutils::util::flush_and_sleep;
+use std::io::Write;
 use std::time::Duration;
 use zbus::Connection;
 
+/// Keyboard LEDs have no event we can wait for, but reading them is cheap.
+/// The IM is also polled, but only when the session bus can't be monitored.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
 #[derive(thiserror::Error, Debug)]
 enum Error {
     #[error("Fcitx/Fcitx5 DBus interface not found")]
     FcitxNotFound,
+    #[error("Connection to Fcitx5 closed")]
+    ConnectionClosed,
     #[error("Zbus error: {0}")]
-    ZbusError(#[from] zbus::Error),
+    Zbus(#[from] zbus::Error),
 }
 
 fn highlight(value: &str) -> String {
@@ -20,10 +27,10 @@ fn leds_state(id: u8) -> String {
     let state = get_leds_state(id).expect("Read keyboard leds state");
     let mut result = String::new();
     if state.num_lock {
-        result.push_str(&*highlight("[Num]"));
+        result.push_str(&highlight("[Num]"));
     }
     if state.caps_lock {
-        result.push_str(&*highlight("[Caps]"));
+        result.push_str(&highlight("[Caps]"));
     }
     result
 }
@@ -32,33 +39,32 @@ fn render(id: u8, current_im: &str, imlist: &[InputMethod]) -> String {
     if current_im.is_empty() {
         return String::new();
     }
+    // An IM missing from the list is shown by its internal name
     let display_name = imlist
         .iter()
         .find(|im| im.name == current_im)
-        .unwrap()
-        .display_name
-        .clone();
+        .map_or(current_im, |im| im.display_name.as_str());
     format!("{} {}", display_name, leds_state(id))
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Error> {
-    // Polling is bad, but there is no other reliable solution on X11...
-    let keyboard_id = get_input_id().expect("Get keyboard ID");
-    let zbus_conn = Connection::session().await.unwrap();
-    let fcitx5_proxy = Fcitx5ControllerProxy::new(&zbus_conn).await.unwrap();
-    let imlist: Vec<InputMethod> = {
-        // This doesn't have display name, so we need another call
-        let current_group = fcitx5_proxy.current_input_method_group().await.unwrap();
-        let active_input_methods: Vec<String> = fcitx5_proxy
-            .input_method_group_info(&current_group)
-            .await
-            .unwrap()
-            .1
-            .into_iter()
-            .map(|t| t.0)
-            .collect();
-        fcitx5_proxy.input_methods().await.ok().map(|list| {
+/// Input methods of a group, with their display names
+async fn load_imlist(
+    fcitx5_proxy: &Fcitx5ControllerProxy<'_>,
+    group: &str,
+) -> Result<Vec<InputMethod>, Error> {
+    // This doesn't have display name, so we need another call
+    let active_input_methods: Vec<String> = fcitx5_proxy
+        .input_method_group_info(group)
+        .await?
+        .1
+        .into_iter()
+        .map(|t| t.0)
+        .collect();
+    fcitx5_proxy
+        .input_methods()
+        .await
+        .ok()
+        .map(|list| {
             list.into_iter()
                 .filter_map(|x| {
                     let mut im: InputMethod = x.into();
@@ -74,16 +80,73 @@ async fn main() -> Result<(), Error> {
                 })
                 .collect()
         })
+        .ok_or(Error::FcitxNotFound)
+}
+
+/// The next IM switch, pending forever when we aren't monitoring
+async fn next_switch(switches: &mut Option<InputMethodSwitches>) -> Option<String> {
+    match switches {
+        Some(switches) => switches.next().await,
+        None => std::future::pending().await,
     }
-    .ok_or(Error::FcitxNotFound)?;
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let keyboard_id = get_input_id().expect("Get keyboard ID");
+    let zbus_conn = Connection::session().await.unwrap();
+    let fcitx5_proxy = Fcitx5ControllerProxy::new(&zbus_conn).await.unwrap();
+    let mut groups_changed = fcitx5_proxy.receive_input_method_groups_changed().await?;
+    let mut current_group = fcitx5_proxy.current_input_method_group().await?;
+    let mut imlist = load_imlist(&fcitx5_proxy, &current_group).await?;
+    // Polling the IM over DBus is the last resort, when the bus policy
+    // doesn't let us see the `CurrentIM` signals
+    let mut switches = match InputMethodSwitches::monitor().await {
+        Ok(switches) => Some(switches),
+        Err(e) => {
+            eprintln!("Monitor IM switches: {}, polling instead", e);
+            None
+        }
+    };
+    let mut current_im = fcitx5_proxy.current_input_method().await?;
     let mut old = String::new();
+    let mut old_im = String::new();
     loop {
-        let current_im = fcitx5_proxy.current_input_method().await?;
+        let mut group_changed = false;
+        tokio::select! {
+            signal = groups_changed.next() => {
+                signal.ok_or(Error::ConnectionClosed)?;
+                group_changed = true;
+                current_im = fcitx5_proxy.current_input_method().await?;
+            }
+            switch = next_switch(&mut switches) => match switch {
+                Some(name) => current_im = name,
+                None => {
+                    eprintln!("Monitor connection closed, polling the IM instead");
+                    switches = None;
+                }
+            },
+            _ = tokio::time::sleep(POLL_INTERVAL) => {
+                if switches.is_none() {
+                    current_im = fcitx5_proxy.current_input_method().await?;
+                }
+            }
+        }
+        // An IM may also have been added to the group in the config tool.
+        // Only reload once per unknown IM, it may stay unknown.
+        let unknown_im = current_im != old_im
+            && !current_im.is_empty()
+            && !imlist.iter().any(|im| im.name == current_im);
+        if group_changed || unknown_im {
+            current_group = fcitx5_proxy.current_input_method_group().await?;
+            imlist = load_imlist(&fcitx5_proxy, &current_group).await?;
+        }
+        old_im.clone_from(&current_im);
         let now = render(keyboard_id, &current_im, &imlist);
         if !now.is_empty() && now != old {
             println!("{}", now);
+            std::io::stdout().flush().expect("Flush stdout");
             old = now;
         }
-        flush_and_sleep(Duration::from_millis(50));
     }
 }
@@ -1,17 +1,128 @@
 use notify::{Config, RecommendedWatcher, RecursiveMode, Watcher};
 use std::io::{BufRead, BufReader};
-use std::path::Path;
 use std::process::{Command, Stdio};
 use std::sync::mpsc::channel;
+use std::sync::{Mutex, OnceLock};
 use std::thread::{sleep, spawn};
 use std::time::Duration;
 
-use obutils::battery::get_battery_info;
-use obutils::brightness::get_brightness;
+use obutils::battery::{
+    get_battery_info, BatteryAlarm, BatteryEstimator, BatteryInfo, BatteryLevel, ChargeStatus,
+};
+use obutils::battery_health::{self, Date};
+use obutils::brightness::{Backlight, BrightnessControl, BrightnessCurve};
+use obutils::ddc::DdcDisplay;
+use obutils::notification::{send_notification, Urgency};
 use obutils::pulseaudio::get_sink_state;
+use obutils::util::{humanize_duration, Percent};
+
+static ESTIMATOR: Mutex<BatteryEstimator> = Mutex::new(BatteryEstimator::new());
+static BACKLIGHT: OnceLock<Option<Backlight>> = OnceLock::new();
+static DISPLAYS: OnceLock<Vec<Monitor>> = OnceLock::new();
+/// A DDC/CI query takes about 40ms per monitor, too slow to do on every render
+const DDC_REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// An external monitor with its last known brightness, `None` when it didn't answer
+struct Monitor {
+    display: DdcDisplay,
+    brightness: Mutex<Option<u8>>,
+}
+
+impl Monitor {
+    /// Ask the monitor again, return whether the brightness changed
+    fn refresh(&self) -> bool {
+        let brightness = self.display.get_brightness().ok();
+        let mut cached = self.brightness.lock().unwrap();
+        let changed = *cached != brightness;
+        *cached = brightness;
+        changed
+    }
+}
+
+#[derive(Debug)]
+struct Arguments {
+    /// Warn when the battery drops to this level
+    pub low: Percent,
+    /// Run `critical_command` when the battery drops to this level
+    pub critical: Percent,
+    /// Shell command to run at the critical level, e.g. `systemctl suspend`
+    pub critical_command: Option<String>,
+    /// Name of the backlight under `/sys/class/backlight`, auto-detected if `None`
+    pub backlight: Option<String>,
+    /// How the displayed brightness percentage maps to raw backlight values
+    pub curve: BrightnessCurve,
+    /// Also show the brightness of external monitors through DDC/CI
+    pub ddc: bool,
+}
+
+fn print_usage_and_exit(exe_name: &str) -> ! {
+    eprintln!(
+        "Usage: {} [--low percent] [--critical percent] [--critical-command command] [--backlight name] [--curve linear|exponential|cie] [--ddc]",
+        exe_name
+    );
+    std::process::exit(1);
+}
+
+fn parse_args() -> Arguments {
+    let mut args = std::env::args();
+    let exe_name = args.next().unwrap();
+    let mut result = Arguments {
+        low: Percent::from_normalized(0.15),
+        critical: Percent::from_normalized(0.05),
+        critical_command: None,
+        backlight: None,
+        curve: BrightnessCurve::default(),
+        ddc: false,
+    };
+    let parse_percent = |value: Option<String>| -> Percent {
+        match value.and_then(|value| value.parse::<u8>().ok()) {
+            Some(value) if value <= 100 => Percent::from(value as f64, 100.0),
+            _ => print_usage_and_exit(&exe_name),
+        }
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--low" => result.low = parse_percent(args.next()),
+            "--critical" => result.critical = parse_percent(args.next()),
+            "--critical-command" => {
+                result.critical_command = Some(
+                    args.next()
+                        .unwrap_or_else(|| print_usage_and_exit(&exe_name)),
+                )
+            }
+            "--backlight" => {
+                result.backlight = Some(
+                    args.next()
+                        .unwrap_or_else(|| print_usage_and_exit(&exe_name)),
+                )
+            }
+            "--curve" => {
+                result.curve = args
+                    .next()
+                    .and_then(|curve| curve.parse().ok())
+                    .unwrap_or_else(|| print_usage_and_exit(&exe_name))
+            }
+            "--ddc" => result.ddc = true,
+            _ => print_usage_and_exit(&exe_name),
+        }
+    }
+    if result.critical.value > result.low.value {
+        print_usage_and_exit(&exe_name);
+    }
+    result
+}
+
+fn backlight() -> Option<&'static Backlight> {
+    BACKLIGHT.get().and_then(Option::as_ref)
+}
 
 fn show_brightness() {
-    let brightness = get_brightness();
+    // Desktop monitors have no backlight to show
+    let brightness = match backlight().map(Backlight::get_brightness) {
+        Some(Ok(brightness)) => brightness,
+        // Gone for a moment, e.g. while its driver is reloaded
+        Some(Err(_)) | None => return,
+    };
     let icon = match brightness {
         0..=19 => "🌑",
         20..=39 => "🌘",
@@ -23,6 +134,16 @@ fn show_brightness() {
     print!("{} {}%", icon, brightness);
 }
 
+fn show_displays() {
+    for monitor in DISPLAYS.get().into_iter().flatten() {
+        // A monitor can be turned off or unplugged at any time
+        match *monitor.brightness.lock().unwrap() {
+            Some(brightness) => print!(" 🖥️ {} {}%", monitor.display.name, brightness),
+            None => print!(" 🖥️ {} ?", monitor.display.name),
+        }
+    }
+}
+
 fn show_volume() {
     let state = get_sink_state();
     let icon = if state.muted {
@@ -38,15 +159,35 @@ fn show_volume() {
 }
 
 fn show_battery() {
-    let info = get_battery_info();
-    let remaining = (info.now as f64 / info.full as f64 * 100.0).clamp(0.0, 100.0);
-    let wear_out = (1.0 - (info.full as f64 / info.full_design as f64)) * 100.0;
-    let icon = if info.charging { '🔌' } else { '🔋' };
-    print!("{}{:3.0}% ({:2.0}% wear)", icon, remaining, wear_out);
+    // Desktops have no battery to show
+    let info = match get_battery_info() {
+        Some(info) => info,
+        None => return,
+    };
+    let wear_out = info.wear().value * 100.0;
+    let icon = match info.status {
+        ChargeStatus::Charging => "⚡",
+        // The adapter can't keep up, e.g. a weak USB-C charger
+        ChargeStatus::Discharging if info.ac_online => "⚠️",
+        ChargeStatus::Discharging => "🔋",
+        ChargeStatus::Full => "🔌",
+        ChargeStatus::NotCharging => "⏸️",
+        ChargeStatus::Unknown => "❔",
+    };
+    print!("{}{:3.0}%", icon, info.remaining().value * 100.0);
+    let estimator = ESTIMATOR.lock().unwrap();
+    if let Some(time) = estimator
+        .time_to_empty(&info)
+        .or_else(|| estimator.time_to_full(&info))
+    {
+        print!(" {}", humanize_duration(time));
+    }
+    print!(" ({:2.0}% wear)", wear_out);
 }
 
 fn print_info() {
     show_brightness();
+    show_displays();
     print!(" ");
     show_volume();
     print!(" ");
@@ -54,7 +195,52 @@ fn print_info() {
     println!();
 }
 
+fn sample_battery() -> Option<BatteryInfo> {
+    let info = get_battery_info()?;
+    ESTIMATOR.lock().unwrap().update(&info);
+    Some(info)
+}
+
+fn alert(level: BatteryLevel, info: &BatteryInfo, critical_command: Option<&str>) {
+    let (icon, summary, urgency) = match level {
+        BatteryLevel::Normal => return,
+        BatteryLevel::Low => ("battery-caution", "Battery low", Urgency::Normal),
+        BatteryLevel::Critical => ("battery-empty", "Battery critically low", Urgency::Critical),
+    };
+    let body = format!("{:.0}% remaining", info.remaining().value * 100.0);
+    if let Err(e) = send_notification(icon, summary, &body, urgency) {
+        eprintln!("Send battery notification: {}", e);
+    }
+    if let (BatteryLevel::Critical, Some(command)) = (level, critical_command) {
+        if let Err(e) = Command::new("sh").arg("-c").arg(command).status() {
+            eprintln!("Run `{}`: {}", command, e);
+        }
+    }
+}
+
 fn main() {
+    let args = parse_args();
+    let mut alarm = BatteryAlarm::new(args.low, args.critical);
+    BACKLIGHT.get_or_init(|| {
+        Backlight::find(args.backlight.as_deref()).map(|backlight| backlight.with_curve(args.curve))
+    });
+    DISPLAYS.get_or_init(|| {
+        if args.ddc {
+            DdcDisplay::list()
+                .into_iter()
+                .map(|display| {
+                    let monitor = Monitor {
+                        display,
+                        brightness: Mutex::new(None),
+                    };
+                    monitor.refresh();
+                    monitor
+                })
+                .collect()
+        } else {
+            Vec::new()
+        }
+    });
     print_info();
 
     // Volume
@@ -78,22 +264,51 @@ fn main() {
         }
     });
 
-    // Battery
-    spawn(|| {
-        sleep(Duration::from_secs(60));
+    // Battery, the first sample is checked right away in case we start
+    // on an almost empty battery
+    let mut recorded_on = None;
+    spawn(move || loop {
+        if let Some(info) = sample_battery() {
+            if let Some(level) = alarm.check(&info) {
+                alert(level, &info, args.critical_command.as_deref());
+            }
+            // Only the first sample of each day is kept, so the history is
+            // only looked at when the day changes
+            let today = Date::today();
+            if recorded_on != Some(today) {
+                recorded_on = Some(today);
+                if let Err(e) = battery_health::record(&info) {
+                    eprintln!("Record battery health: {}", e);
+                }
+            }
+        }
         print_info();
+        sleep(Duration::from_secs(60));
     });
 
+    // External monitors have no change notification
+    if DISPLAYS.get().is_some_and(|monitors| !monitors.is_empty()) {
+        spawn(|| loop {
+            sleep(DDC_REFRESH_INTERVAL);
+            let mut changed = false;
+            for monitor in DISPLAYS.get().into_iter().flatten() {
+                changed |= monitor.refresh();
+            }
+            if changed {
+                print_info();
+            }
+        });
+    }
+
     // Brightness
     let (tx, rx) = channel();
     let watcher_config = Config::default().with_poll_interval(Duration::from_millis(100));
     let mut brightness_watcher = RecommendedWatcher::new(tx, watcher_config).unwrap();
-    brightness_watcher
-        .watch(
-            Path::new("/sys/class/backlight/amdgpu_bl1/brightness"),
-            RecursiveMode::NonRecursive,
-        )
-        .unwrap_or_else(|_| volume_watcher.kill().unwrap());
+    if let Some(backlight) = backlight() {
+        brightness_watcher
+            .watch(&backlight.brightness_path(), RecursiveMode::NonRecursive)
+            .unwrap_or_else(|_| volume_watcher.kill().unwrap());
+    }
     loop {
         if rx.recv().is_ok() {
             print_info();
@@ -0,0 +1,49 @@
+use obutils::removable::{list_removable, HotplugWatcher, RemovableDevice};
+use obutils::util::humanize;
+use obutils::util::DataUnit::Byte;
+use std::thread::sleep;
+use std::time::Duration;
+
+/// Mounting happens a moment after the device shows up, and unmounting sends
+/// no uevent at all, so the list is refreshed regularly too
+const REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+
+fn render(devices: &[RemovableDevice]) -> String {
+    devices
+        .iter()
+        .map(|device| {
+            let name = device.label.as_deref().unwrap_or(&device.name);
+            let size = humanize(Byte(device.size), false);
+            if device.is_mounted() {
+                format!("⏏ {} {}", name, size.trim())
+            } else {
+                format!("⏏ {} {} (not mounted)", name, size.trim())
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("  ")
+}
+
+fn main() {
+    let mut watcher = HotplugWatcher::new().expect("Watch block devices");
+    let mut last_output = None;
+    loop {
+        let output = render(&list_removable());
+        if last_output.as_ref() != Some(&output) {
+            println!("{}", output);
+            last_output = Some(output);
+        }
+        if let Err(e) = watcher.next_event(REFRESH_INTERVAL) {
+            // Usually ENOBUFS, a burst of uevents overflowed the socket.
+            // Events were lost either way, so start over with a fresh watcher.
+            eprintln!("Wait for block device events: {}", e);
+            match HotplugWatcher::new() {
+                Ok(new_watcher) => watcher = new_watcher,
+                Err(e) => {
+                    eprintln!("Watch block devices: {}", e);
+                    sleep(REFRESH_INTERVAL);
+                }
+            }
+        }
+    }
+}
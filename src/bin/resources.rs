@@ -1,34 +1,172 @@
-use obutils::cpu::get_cpu_usage;
-use obutils::disk::get_disk_io;
+use obutils::cpu::{get_cpu_freqs, get_cpu_times, get_load_average, FreqSummary};
+use obutils::disk::{disk_utilizations, get_disk_stats, DiskFilter, DiskIo};
+use obutils::filesystem::{fullest, get_filesystems, get_remote_filesystems, FilesystemUsage};
 use obutils::memory::{get_ram_usage, get_swap_usage};
 use obutils::network::{get_network_io, get_networks};
+use obutils::pressure::{get_pressure, Resource};
+use obutils::process::ProcessSnapshot;
+use obutils::thermal::find_cpu_sensor;
 use obutils::util::DataUnit::{Byte, KiB};
-use obutils::util::{flush_and_sleep, humanize};
+use obutils::util::{flush_and_sleep, humanize, sparkline, Percent};
+use std::sync::Mutex;
+use std::thread::{sleep, spawn};
 use std::time::{Duration, Instant};
 
+/// Used when the sensor doesn't report its critical temperature, e.g. k10temp
+const DEFAULT_CRIT_TEMP: f64 = 100.0;
+/// Highlight the temperature when it gets this close to the critical one
+const CRIT_TEMP_MARGIN: f64 = 15.0;
+/// A network filesystem can take a while to answer, it is checked in the background
+const REMOTE_FS_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Network filesystems as of the last check
+static REMOTE_FILESYSTEMS: Mutex<Vec<FilesystemUsage>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Default)]
+struct Arguments {
+    /// Show a sparkline of each core next to the CPU percentage
+    pub per_core: bool,
+    /// Show the average and max frequency of all cores
+    pub freq: bool,
+    /// Show the pressure stall of the CPU, memory and IO
+    pub pressure: bool,
+    /// Highlight a pressure whose 10 seconds average crosses this percentage
+    pub pressure_threshold: f64,
+    /// Show the load average per CPU and the number of tasks
+    pub load: bool,
+    /// Append the name of the process using the most CPU and memory to their segments
+    pub top: bool,
+    /// Block devices counted in the disk segment
+    pub disk_filter: DiskFilter,
+    /// Show the busiest device and how busy it is
+    pub disk_busy: bool,
+    /// Show the usage of the fullest local filesystem, and of the network ones filling up
+    pub fs: bool,
+    /// Highlight the fullest filesystem when its usage crosses this percentage
+    pub fs_threshold: f64,
+}
+
+fn print_usage_and_exit(exe_name: &str) -> ! {
+    eprintln!(
+        "Usage: {} [--per-core] [--freq] [--pressure] [--pressure-threshold percent] [--load] [--top] [--disk-include classes] [--disk-exclude classes] [--disk-busy] [--fs] [--fs-threshold percent]\n\
+        Device classes are loop, ram, zram, dm and md, separated by commas. By default\n\
+        only physical disks are counted.",
+        exe_name
+    );
+    std::process::exit(1);
+}
+
+fn parse_args() -> Arguments {
+    let mut args = std::env::args();
+    let exe_name = args.next().unwrap();
+    let mut result = Arguments {
+        pressure_threshold: 10.0,
+        fs_threshold: 90.0,
+        ..Arguments::default()
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--per-core" => result.per_core = true,
+            "--freq" => result.freq = true,
+            "--pressure" => result.pressure = true,
+            "--load" => result.load = true,
+            "--top" => result.top = true,
+            "--disk-busy" => result.disk_busy = true,
+            "--fs" => result.fs = true,
+            "--fs-threshold" => {
+                result.fs_threshold = args
+                    .next()
+                    .and_then(|value| value.parse().ok())
+                    .unwrap_or_else(|| print_usage_and_exit(&exe_name))
+            }
+            "--disk-include" => {
+                result.disk_filter.include = args
+                    .next()
+                    .and_then(|list| DiskFilter::parse_classes(&list).ok())
+                    .unwrap_or_else(|| print_usage_and_exit(&exe_name))
+            }
+            "--disk-exclude" => {
+                result.disk_filter.exclude = args
+                    .next()
+                    .and_then(|list| DiskFilter::parse_classes(&list).ok())
+                    .unwrap_or_else(|| print_usage_and_exit(&exe_name))
+            }
+            "--pressure-threshold" => {
+                result.pressure_threshold = args
+                    .next()
+                    .and_then(|value| value.parse().ok())
+                    .unwrap_or_else(|| print_usage_and_exit(&exe_name))
+            }
+            _ => print_usage_and_exit(&exe_name),
+        }
+    }
+    result
+}
+
 fn main() {
+    let args = parse_args();
     let interface = get_networks().unwrap().wireless.unwrap();
     let interface = interface.as_str();
 
     let highlight_color = "foreground='#ff9944'";
     let separator = "  ";
 
-    let mut old_cpu = get_cpu_usage();
+    let cpu_sensor = find_cpu_sensor();
+    let mut old_cpu = get_cpu_times();
+    let mut old_processes = if args.top {
+        ProcessSnapshot::take()
+    } else {
+        ProcessSnapshot::default()
+    };
     let mut old_net_io = get_network_io(interface);
-    let mut old_disk_io = get_disk_io();
+    let mut old_disk_stats = get_disk_stats(&args.disk_filter);
+    let mut old_disk_time = Instant::now();
+    if args.fs {
+        spawn(|| loop {
+            let remote = get_remote_filesystems();
+            *REMOTE_FILESYSTEMS.lock().unwrap() = remote;
+            sleep(REMOTE_FS_INTERVAL);
+        });
+    }
 
     loop {
         let now = Instant::now();
 
         print!("<span {}>C:</span> ", highlight_color);
-        let cpu = get_cpu_usage();
-        // Handle the first iteration to avoid division by zero
-        if cpu.total == old_cpu.total {
-            print!("{:3}%", 0);
+        let cpu = get_cpu_times();
+        let processes = if args.top {
+            ProcessSnapshot::take()
         } else {
-            let work_diff = (cpu.work - old_cpu.work) as f64;
-            let total_diff = (cpu.total - old_cpu.total) as f64;
-            print!("{:3.0}%", (work_diff / total_diff * 100f64).round());
+            ProcessSnapshot::default()
+        };
+        // The delta is zero on the first iteration, so it shows 0%
+        let busy = cpu.total.delta(&old_cpu.total).busy;
+        print!("{:3.0}%", (busy.value * 100f64).round());
+        if args.per_core {
+            let cores: Vec<Percent> = cpu
+                .core_deltas(&old_cpu)
+                .iter()
+                .map(|core| core.busy)
+                .collect();
+            print!(" {}", sparkline(&cores));
+        }
+        if args.freq {
+            if let Some(freq) = FreqSummary::of(&get_cpu_freqs()) {
+                print!(" {:.0}/{:.0} MHz", freq.average, freq.max);
+            }
+        }
+        if let Some(sensor) = &cpu_sensor {
+            if let Some(temp) = sensor.read() {
+                let crit = sensor.crit.unwrap_or(DEFAULT_CRIT_TEMP);
+                if temp >= crit - CRIT_TEMP_MARGIN {
+                    print!(" <span {}>{:.0}°C</span>", highlight_color, temp);
+                } else {
+                    print!(" {:.0}°C", temp);
+                }
+            }
+        }
+        if let Some(usage) = processes.top_cpu(&old_processes, 1).first() {
+            print!(" {}", usage.process.name);
         }
         old_cpu = cpu;
         print!("{}", separator);
@@ -43,6 +181,10 @@ fn main() {
             humanize(KiB(ram_info.total as f64), false).trim(),
             ram_percent
         );
+        if let Some(process) = processes.top_rss(1).first() {
+            print!(" {}", process.name);
+        }
+        old_processes = processes;
         print!("{}", separator);
 
         print!("<span {}>S:</span> ", highlight_color);
@@ -61,6 +203,36 @@ fn main() {
         }
         print!("{}", separator);
 
+        if args.load {
+            let load = get_load_average();
+            let [one, five, fifteen] = load.normalized();
+            print!(
+                "<span {}>L:</span> {:.0}% {:.0}% {:.0}% ({}/{}){}",
+                highlight_color,
+                one.value * 100.0,
+                five.value * 100.0,
+                fifteen.value * 100.0,
+                load.running,
+                load.total,
+                separator
+            );
+        }
+
+        if args.pressure {
+            print!("<span {}>P:</span>", highlight_color);
+            for resource in [Resource::Cpu, Resource::Memory, Resource::Io] {
+                match get_pressure(resource) {
+                    Ok(pressure) if pressure.some.avg10 >= args.pressure_threshold => print!(
+                        " {} <span {}>{:.0}%</span>",
+                        resource, highlight_color, pressure.some.avg10
+                    ),
+                    Ok(pressure) => print!(" {} {:.0}%", resource, pressure.some.avg10),
+                    Err(_) => print!(" {} N/A", resource),
+                }
+            }
+            print!("{}", separator);
+        }
+
         let net_io = get_network_io(interface);
         let received_diff = net_io.received - old_net_io.received;
         let sent_diff = net_io.sent - old_net_io.sent;
@@ -76,15 +248,50 @@ fn main() {
             "<span weight='bold' size='x-large' {}>🖴</span> ",
             highlight_color
         );
-        let disk_io = get_disk_io();
-        let read_diff = disk_io.read - old_disk_io.read;
-        let write_diff = disk_io.write - old_disk_io.write;
-        old_disk_io = disk_io;
+        let disk_stats = get_disk_stats(&args.disk_filter);
+        let disk_time = Instant::now();
+        let disk_io = DiskIo::between(&disk_stats, &old_disk_stats);
         print!(
             "➡️ {} ⬅️ {}",
-            humanize(Byte(read_diff), true),
-            humanize(Byte(write_diff), true)
+            humanize(Byte(disk_io.read), true),
+            humanize(Byte(disk_io.write), true)
         );
+        if args.disk_busy {
+            let elapsed = disk_time.duration_since(old_disk_time);
+            if let Some(disk) = disk_utilizations(&disk_stats, &old_disk_stats, elapsed).first() {
+                print!(" {} {:.0}%", disk.name, disk.busy.value * 100.0);
+            }
+        }
+        old_disk_stats = disk_stats;
+        old_disk_time = disk_time;
+
+        if args.fs {
+            let show = |fs: &FilesystemUsage| {
+                let percent = fs.usage().value * 100.0;
+                let usage = format!(
+                    "{} {}/{} ({:.0}%)",
+                    fs.mount.mount_point.display(),
+                    humanize(Byte(fs.used), false).trim(),
+                    humanize(Byte(fs.used + fs.avail), false).trim(),
+                    percent
+                );
+                if percent >= args.fs_threshold {
+                    print!("{}<span {}>{}</span>", separator, highlight_color, usage);
+                } else {
+                    print!("{}{}", separator, usage);
+                }
+            };
+            let filesystems = get_filesystems();
+            if let Some(fs) = fullest(&filesystems) {
+                show(fs);
+            }
+            // Network shares are only worth a mention when they are filling up
+            for fs in REMOTE_FILESYSTEMS.lock().unwrap().iter() {
+                if !fs.mount.is_read_only() && fs.usage().value * 100.0 >= args.fs_threshold {
+                    show(fs);
+                }
+            }
+        }
 
         println!();
         let next_tick = now.checked_add(Duration::from_secs(1)).unwrap();
@@ -1,13 +1,462 @@
-use crate::util;
+use std::fs;
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::thread::sleep;
+use std::time::Duration;
+use zbus::dbus_proxy;
 
-fn path_of(name: &str) -> String {
-    format!("/sys/class/backlight/amdgpu_bl1/{}", name)
+use crate::ddc::{DdcDisplay, DdcError, VCP_LUMINANCE};
+
+const BACKLIGHT_DIR: &str = "/sys/class/backlight";
+/// Interval between two writes when fading
+const FADE_INTERVAL: Duration = Duration::from_millis(15);
+
+/// Reused across writes, a fade does dozens of them
+static SYSTEM_BUS: OnceLock<zbus::blocking::Connection> = OnceLock::new();
+
+/// Backlight interface type, from the most to the least preferred, the same
+/// order systemd-backlight uses
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BacklightType {
+    /// Controlled by the firmware, e.g. `acpi_video0`
+    Firmware,
+    /// Controlled by a platform driver, e.g. `thinkpad_screen`
+    Platform,
+    /// Controlled by the GPU driver, e.g. `intel_backlight`, `amdgpu_bl1`
+    Raw,
+}
+
+impl BacklightType {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim() {
+            "firmware" => Some(BacklightType::Firmware),
+            "platform" => Some(BacklightType::Platform),
+            "raw" => Some(BacklightType::Raw),
+            _ => None,
+        }
+    }
+}
+
+/// How percentages map to raw backlight values. Perceived brightness is far
+/// from linear, most of the visible change happens in the lowest raw values.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum BrightnessCurve {
+    #[default]
+    Linear,
+    /// `raw = (100^percent - 1) / 99`
+    Exponential,
+    /// Percent is CIE 1976 lightness (L*), raw is relative luminance
+    Cie,
+}
+
+/// Base of the exponential curve, the larger the flatter the bottom
+const EXPONENTIAL_BASE: f64 = 100.0;
+
+impl BrightnessCurve {
+    /// Map a percentage in 0..=1 to a fraction of the max raw value
+    pub fn to_raw(self, percent: f64) -> f64 {
+        let percent = percent.clamp(0.0, 1.0);
+        match self {
+            BrightnessCurve::Linear => percent,
+            BrightnessCurve::Exponential => {
+                (EXPONENTIAL_BASE.powf(percent) - 1.0) / (EXPONENTIAL_BASE - 1.0)
+            }
+            BrightnessCurve::Cie => {
+                let lightness = percent * 100.0;
+                if lightness > 8.0 {
+                    ((lightness + 16.0) / 116.0).powi(3)
+                } else {
+                    lightness / 903.3
+                }
+            }
+        }
+    }
+
+    /// Inverse of [`to_raw`](Self::to_raw)
+    pub fn from_raw(self, raw: f64) -> f64 {
+        let raw = raw.clamp(0.0, 1.0);
+        match self {
+            BrightnessCurve::Linear => raw,
+            BrightnessCurve::Exponential => {
+                (raw * (EXPONENTIAL_BASE - 1.0) + 1.0).ln() / EXPONENTIAL_BASE.ln()
+            }
+            BrightnessCurve::Cie => {
+                let lightness = if raw > 0.008856 {
+                    116.0 * raw.cbrt() - 16.0
+                } else {
+                    903.3 * raw
+                };
+                lightness / 100.0
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for BrightnessCurve {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(BrightnessCurve::Linear),
+            "exponential" => Ok(BrightnessCurve::Exponential),
+            "cie" => Ok(BrightnessCurve::Cie),
+            _ => Err(format!("Unknown brightness curve: {}", s)),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum BrightnessError {
+    #[error("Write brightness of {0}: {1}")]
+    Io(String, std::io::Error),
+    #[error("Read {1} of {0}: {2}")]
+    Read(String, &'static str, std::io::Error),
+    #[error("Backlight {0} has no brightness range")]
+    NoRange(String),
+    #[error("Set brightness through logind: {0}")]
+    Logind(#[from] zbus::Error),
+    #[error("DDC/CI error: {0}")]
+    Ddc(#[from] DdcError),
 }
 
-/// Brightness in percent
-pub fn get_brightness() -> u8 {
-    let current = util::read_unsigned(&path_of("brightness")).expect("Read brightness");
-    let max = util::read_unsigned(&path_of("max_brightness")).expect("Read max brightness");
-    let percent = (current as f64 / max as f64 * 100f64).round();
-    percent as u8
+/// Anything whose brightness can be controlled in percent, either a laptop
+/// backlight or an external monitor
+pub trait BrightnessControl {
+    fn name(&self) -> &str;
+    /// Brightness in percent
+    fn get_brightness(&self) -> Result<u8, BrightnessError>;
+    /// Set brightness in percent, values above 100 are clamped
+    fn set_brightness(&self, percent: u8) -> Result<(), BrightnessError>;
+    /// Change brightness by `delta` percent, return the new brightness
+    fn step_brightness(&self, delta: i8) -> Result<u8, BrightnessError> {
+        let target = (self.get_brightness()? as i16 + delta as i16).clamp(0, 100) as u8;
+        self.set_brightness(target)?;
+        Ok(target)
+    }
+}
+
+/// Unprivileged users can't write to sysfs, but logind lets the session owner
+/// change the brightness
+#[dbus_proxy(
+    interface = "org.freedesktop.login1.Session",
+    default_service = "org.freedesktop.login1",
+    default_path = "/org/freedesktop/login1/session/auto"
+)]
+trait LogindSession {
+    fn set_brightness(&self, subsystem: &str, name: &str, brightness: u32) -> zbus::Result<()>;
+}
+
+fn system_bus() -> zbus::Result<&'static zbus::blocking::Connection> {
+    if let Some(connection) = SYSTEM_BUS.get() {
+        return Ok(connection);
+    }
+    let connection = zbus::blocking::Connection::system()?;
+    Ok(SYSTEM_BUS.get_or_init(|| connection))
+}
+
+/// A device under `/sys/class/backlight`
+#[derive(Debug, Clone)]
+pub struct Backlight {
+    pub name: String,
+    pub kind: BacklightType,
+    pub path: PathBuf,
+    pub curve: BrightnessCurve,
+}
+
+impl Backlight {
+    fn open(path: PathBuf) -> Option<Self> {
+        let kind = BacklightType::parse(&fs::read_to_string(path.join("type")).ok()?)?;
+        Some(Backlight {
+            name: path.file_name()?.to_string_lossy().into_owned(),
+            kind,
+            path,
+            curve: BrightnessCurve::default(),
+        })
+    }
+
+    /// Use `curve` for all percentages read and written through this handle
+    pub fn with_curve(self, curve: BrightnessCurve) -> Self {
+        Backlight { curve, ..self }
+    }
+
+    fn list_in(dir: &Path) -> Vec<Backlight> {
+        let mut backlights: Vec<Backlight> = match fs::read_dir(dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| Backlight::open(entry.ok()?.path()))
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        backlights.sort_by(|a, b| a.kind.cmp(&b.kind).then_with(|| a.name.cmp(&b.name)));
+        backlights
+    }
+
+    /// All backlights, the preferred one first
+    pub fn list() -> Vec<Backlight> {
+        Backlight::list_in(Path::new(BACKLIGHT_DIR))
+    }
+
+    pub(crate) fn find_in(dir: &Path, name: Option<&str>) -> Option<Backlight> {
+        let mut backlights = Backlight::list_in(dir).into_iter();
+        match name {
+            Some(name) => backlights.find(|b| b.name == name),
+            None => backlights.next(),
+        }
+    }
+
+    /// Find the backlight with the given name, or the preferred one if `name` is `None`.
+    /// Return `None` if there is no such backlight, e.g. on desktops.
+    pub fn find(name: Option<&str>) -> Option<Backlight> {
+        Backlight::find_in(Path::new(BACKLIGHT_DIR), name)
+    }
+
+    /// The file to watch for brightness changes
+    pub fn brightness_path(&self) -> PathBuf {
+        self.path.join("brightness")
+    }
+
+    /// The backlight can disappear at any time, e.g. when its driver is reloaded
+    fn read(&self, file: &'static str) -> Result<u64, BrightnessError> {
+        fs::read_to_string(self.path.join(file))
+            .and_then(|content| {
+                content
+                    .trim()
+                    .parse()
+                    .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+            })
+            .map_err(|e| BrightnessError::Read(self.name.clone(), file, e))
+    }
+
+    fn read_max(&self) -> Result<u64, BrightnessError> {
+        match self.read("max_brightness")? {
+            0 => Err(BrightnessError::NoRange(self.name.clone())),
+            max => Ok(max),
+        }
+    }
+
+    /// Brightness in the range 0..=1, following the curve
+    fn get_exact(&self, max: u64) -> Result<f64, BrightnessError> {
+        Ok(self
+            .curve
+            .from_raw(self.read("brightness")? as f64 / max as f64))
+    }
+
+    /// Brightness in percent
+    pub fn get_brightness(&self) -> Result<u8, BrightnessError> {
+        let max = self.read_max()?;
+        Ok((self.get_exact(max)? * 100f64).round() as u8)
+    }
+
+    fn raw_of(&self, percent: f64, max: u64) -> u64 {
+        (self.curve.to_raw(percent) * max as f64).round() as u64
+    }
+
+    fn write_raw(&self, value: u64) -> Result<(), BrightnessError> {
+        match fs::write(self.brightness_path(), value.to_string()) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+                let session = LogindSessionProxyBlocking::new(system_bus()?)?;
+                session.set_brightness("backlight", &self.name, value as u32)?;
+                Ok(())
+            }
+            Err(e) => Err(BrightnessError::Io(self.name.clone(), e)),
+        }
+    }
+
+    /// Set brightness in percent, values above 100 are clamped
+    pub fn set_brightness(&self, percent: u8) -> Result<(), BrightnessError> {
+        let max = self.read_max()?;
+        self.write_raw(self.raw_of(percent.min(100) as f64 / 100f64, max))
+    }
+
+    /// Change brightness by `delta` percent, return the new brightness
+    pub fn step_brightness(&self, delta: i8) -> Result<u8, BrightnessError> {
+        let max = self.read_max()?;
+        let current = self.read("brightness")?;
+        let target = (self.get_brightness()? as i16 + delta as i16).clamp(0, 100) as u8;
+        let mut raw = self.raw_of(target as f64 / 100f64, max);
+        // With a steep curve and few raw levels, a small step can land on the
+        // same raw value and get stuck there, so move at least one level
+        if raw == current && delta > 0 {
+            raw = (current + 1).min(max);
+        } else if raw == current && delta < 0 {
+            raw = current.saturating_sub(1);
+        }
+        self.write_raw(raw)?;
+        self.get_brightness()
+    }
+
+    /// Gradually change brightness to `percent` over `duration`. The steps are
+    /// evenly spaced on the curve, so the fade looks linear.
+    pub fn fade_to(&self, percent: u8, duration: Duration) -> Result<(), BrightnessError> {
+        let max = self.read_max()?;
+        let start = self.get_exact(max)?;
+        let target = percent.min(100) as f64 / 100f64;
+        let steps = (duration.as_millis() / FADE_INTERVAL.as_millis()).max(1) as u32;
+        let mut last_written = None;
+        for step in 1..=steps {
+            let value = self.raw_of(start + (target - start) * step as f64 / steps as f64, max);
+            // Small ranges like acpi_video0 (0..=15) would write the same value many times
+            if last_written != Some(value) {
+                self.write_raw(value)?;
+                last_written = Some(value);
+            }
+            if step < steps {
+                sleep(FADE_INTERVAL);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl BrightnessControl for Backlight {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn get_brightness(&self) -> Result<u8, BrightnessError> {
+        Backlight::get_brightness(self)
+    }
+
+    fn set_brightness(&self, percent: u8) -> Result<(), BrightnessError> {
+        Backlight::set_brightness(self, percent)
+    }
+
+    fn step_brightness(&self, delta: i8) -> Result<u8, BrightnessError> {
+        Backlight::step_brightness(self, delta)
+    }
+}
+
+impl<D: Read + Write> BrightnessControl for DdcDisplay<D> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn get_brightness(&self) -> Result<u8, BrightnessError> {
+        let (current, max) = self.get_vcp(VCP_LUMINANCE)?;
+        Ok((current as f64 / max as f64 * 100f64).round() as u8)
+    }
+
+    fn set_brightness(&self, percent: u8) -> Result<(), BrightnessError> {
+        let (_, max) = self.get_vcp(VCP_LUMINANCE)?;
+        let value = (percent.min(100) as f64 / 100f64 * max as f64).round() as u16;
+        self.set_vcp(VCP_LUMINANCE, value)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fake_backlight(root: &Path, name: &str, kind: &str) {
+        let dir = root.join(name);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("type"), format!("{}\n", kind)).unwrap();
+        fs::write(dir.join("brightness"), "30\n").unwrap();
+        fs::write(dir.join("max_brightness"), "120\n").unwrap();
+    }
+
+    #[test]
+    fn firmware_is_preferred_over_platform_and_raw() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fake_backlight(root, "intel_backlight", "raw");
+        fake_backlight(root, "acpi_video0", "firmware");
+        fake_backlight(root, "thinkpad_screen", "platform");
+
+        let preferred = Backlight::find_in(root, None).unwrap();
+        assert_eq!(preferred.name, "acpi_video0");
+        assert_eq!(preferred.get_brightness().unwrap(), 25);
+        let chosen = Backlight::find_in(root, Some("intel_backlight")).unwrap();
+        assert_eq!(chosen.kind, BacklightType::Raw);
+        assert!(Backlight::find_in(root, Some("amdgpu_bl1")).is_none());
+    }
+
+    #[test]
+    fn set_and_step_write_raw_values() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fake_backlight(root, "intel_backlight", "raw");
+        let backlight = Backlight::find_in(root, None).unwrap();
+
+        backlight.set_brightness(50).unwrap();
+        assert_eq!(backlight.read("brightness").unwrap(), 60);
+        assert_eq!(backlight.step_brightness(-60).unwrap(), 0);
+        assert_eq!(backlight.step_brightness(5).unwrap(), 5);
+        assert_eq!(backlight.read("brightness").unwrap(), 6);
+        backlight.fade_to(100, Duration::from_millis(50)).unwrap();
+        assert_eq!(backlight.read("brightness").unwrap(), 120);
+    }
+
+    #[test]
+    fn missing_or_empty_backlight_is_an_error() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fake_backlight(root, "intel_backlight", "raw");
+        let backlight = Backlight::find_in(root, None).unwrap();
+
+        fs::write(root.join("intel_backlight/max_brightness"), "0\n").unwrap();
+        assert!(matches!(
+            backlight.get_brightness(),
+            Err(BrightnessError::NoRange(_))
+        ));
+        fs::remove_dir_all(root.join("intel_backlight")).unwrap();
+        assert!(matches!(
+            backlight.step_brightness(5),
+            Err(BrightnessError::Read(_, "max_brightness", _))
+        ));
+        assert!(backlight.fade_to(50, Duration::ZERO).is_err());
+    }
+
+    #[test]
+    fn curves_are_inverse_of_each_other() {
+        for curve in [
+            BrightnessCurve::Linear,
+            BrightnessCurve::Exponential,
+            BrightnessCurve::Cie,
+        ] {
+            assert_eq!(curve.to_raw(0.0), 0.0);
+            assert!((curve.to_raw(1.0) - 1.0).abs() < 1e-3);
+            for percent in [0.02, 0.05, 0.2, 0.5, 0.95] {
+                assert!((curve.from_raw(curve.to_raw(percent)) - percent).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn perceptual_curves_are_below_linear() {
+        for curve in [BrightnessCurve::Exponential, BrightnessCurve::Cie] {
+            assert!(curve.to_raw(0.2) < 0.05);
+            assert!(curve.to_raw(0.5) < 0.5);
+        }
+    }
+
+    #[test]
+    fn stepping_never_gets_stuck_on_coarse_curve() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fake_backlight(root, "acpi_video0", "firmware");
+        fs::write(root.join("acpi_video0/max_brightness"), "15\n").unwrap();
+        fs::write(root.join("acpi_video0/brightness"), "0\n").unwrap();
+        let backlight = Backlight::find_in(root, None)
+            .unwrap()
+            .with_curve(BrightnessCurve::Cie);
+
+        backlight.step_brightness(5).unwrap();
+        assert_eq!(backlight.read("brightness").unwrap(), 1);
+        backlight.step_brightness(-5).unwrap();
+        assert_eq!(backlight.read("brightness").unwrap(), 0);
+    }
+
+    #[test]
+    fn ddc_display_is_controlled_in_percent() {
+        use crate::ddc::tests::FakeMonitor;
+
+        let display = DdcDisplay::with_device("i2c-5".to_string(), FakeMonitor::new(20, 80));
+        let control: &dyn BrightnessControl = &display;
+        assert_eq!(control.get_brightness().unwrap(), 25);
+        assert_eq!(control.step_brightness(25).unwrap(), 50);
+        assert_eq!(display.get_vcp(VCP_LUMINANCE).unwrap(), (40, 80));
+    }
 }
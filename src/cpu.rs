@@ -1,4 +1,9 @@
-use crate::util::read_lines;
+use std::fs;
+use std::path::Path;
+
+use crate::util::{read_lines, Percent};
+
+const CPU_SYSFS_DIR: &str = "/sys/devices/system/cpu";
 
 #[derive(Debug, Default, Copy, Clone)]
 pub struct CpuUsage {
@@ -6,23 +11,417 @@ pub struct CpuUsage {
     pub total: u64,
 }
 
+/// Time spent in each mode, in USER_HZ, as reported by a `cpu` line of `/proc/stat`
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct CpuTimes {
+    pub user: u64,
+    pub nice: u64,
+    pub system: u64,
+    pub idle: u64,
+    pub iowait: u64,
+    pub irq: u64,
+    pub softirq: u64,
+    pub steal: u64,
+    /// Already counted in `user`
+    pub guest: u64,
+    /// Already counted in `nice`
+    pub guest_nice: u64,
+}
+
+impl CpuTimes {
+    /// Parse the numbers after the `cpu`/`cpuN` label, older kernels have fewer columns
+    fn parse(columns: &[&str]) -> CpuTimes {
+        let column = |index: usize, name: &str| -> u64 {
+            columns.get(index).map_or(0, |value| {
+                value
+                    .parse()
+                    .unwrap_or_else(|_| panic!("Read CPU `{}` time", name))
+            })
+        };
+        CpuTimes {
+            user: column(0, "user"),
+            nice: column(1, "nice"),
+            system: column(2, "system"),
+            idle: column(3, "idle"),
+            iowait: column(4, "iowait"),
+            irq: column(5, "irq"),
+            softirq: column(6, "softirq"),
+            steal: column(7, "steal"),
+            guest: column(8, "guest"),
+            guest_nice: column(9, "guest_nice"),
+        }
+    }
+
+    /// Time not spent idle or waiting for IO
+    pub fn work(&self) -> u64 {
+        self.user + self.nice + self.system + self.irq + self.softirq + self.steal
+    }
+
+    /// Guest time is excluded since it is already part of user and nice time
+    pub fn total(&self) -> u64 {
+        self.work() + self.idle + self.iowait
+    }
+
+    pub fn usage(&self) -> CpuUsage {
+        CpuUsage {
+            work: self.work(),
+            total: self.total(),
+        }
+    }
+
+    /// Share of each mode between an older snapshot and this one
+    pub fn delta(&self, old: &CpuTimes) -> CpuModes {
+        let total = self.total().saturating_sub(old.total());
+        let share = |now: u64, old: u64| -> Percent {
+            if total == 0 {
+                Percent::default()
+            } else {
+                Percent::from(now.saturating_sub(old) as f64, total as f64)
+            }
+        };
+        CpuModes {
+            user: share(self.user, old.user),
+            nice: share(self.nice, old.nice),
+            system: share(self.system, old.system),
+            idle: share(self.idle, old.idle),
+            iowait: share(self.iowait, old.iowait),
+            irq: share(self.irq, old.irq),
+            softirq: share(self.softirq, old.softirq),
+            steal: share(self.steal, old.steal),
+            guest: share(self.guest, old.guest),
+            guest_nice: share(self.guest_nice, old.guest_nice),
+            busy: share(self.work(), old.work()),
+        }
+    }
+}
+
+/// Share of time spent in each mode between two [`CpuTimes`]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct CpuModes {
+    pub user: Percent,
+    pub nice: Percent,
+    pub system: Percent,
+    pub idle: Percent,
+    pub iowait: Percent,
+    pub irq: Percent,
+    pub softirq: Percent,
+    pub steal: Percent,
+    pub guest: Percent,
+    pub guest_nice: Percent,
+    /// Everything except idle and iowait
+    pub busy: Percent,
+}
+
+/// All CPUs combined plus each core, from `/proc/stat`
+#[derive(Debug, Default, Clone)]
+pub struct CpuSnapshot {
+    pub total: CpuTimes,
+    /// Indexed by the core number in `cpuN`
+    pub cores: Vec<CpuTimes>,
+}
+
+impl CpuSnapshot {
+    fn parse(lines: impl Iterator<Item = String>) -> CpuSnapshot {
+        let mut snapshot = CpuSnapshot::default();
+        for line in lines {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            match parts.first() {
+                Some(&"cpu") => snapshot.total = CpuTimes::parse(&parts[1..]),
+                Some(label) if label.starts_with("cpu") => {
+                    let index: usize = label[3..].parse().expect("Read CPU core number");
+                    if snapshot.cores.len() <= index {
+                        snapshot.cores.resize(index + 1, CpuTimes::default());
+                    }
+                    snapshot.cores[index] = CpuTimes::parse(&parts[1..]);
+                }
+                // The `cpu` lines always come first
+                _ => break,
+            }
+        }
+        snapshot
+    }
+
+    /// Per-mode share of each core between an older snapshot and this one.
+    /// Cores that went offline in between are reported as idle.
+    pub fn core_deltas(&self, old: &CpuSnapshot) -> Vec<CpuModes> {
+        self.cores
+            .iter()
+            .enumerate()
+            .map(|(index, core)| match old.cores.get(index) {
+                Some(old) => core.delta(old),
+                None => CpuModes::default(),
+            })
+            .collect()
+    }
+}
+
+pub fn get_cpu_times() -> CpuSnapshot {
+    CpuSnapshot::parse(read_lines("/proc/stat"))
+}
+
 pub fn get_cpu_usage() -> CpuUsage {
-    for line in read_lines("/proc/stat") {
+    get_cpu_times().total.usage()
+}
+
+/// Frequency scaling state of a core, from `cpuN/cpufreq`. Frequencies are in kHz.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CpuFreq {
+    pub core: usize,
+    pub current: u64,
+    pub min: u64,
+    pub max: u64,
+    pub governor: Option<String>,
+    /// Only available with `intel_pstate`/`amd-pstate` in active mode
+    pub energy_performance_preference: Option<String>,
+}
+
+/// Frequencies of all cores combined, in MHz
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct FreqSummary {
+    pub average: f64,
+    pub max: f64,
+}
+
+impl FreqSummary {
+    /// `None` if there is no core to summarize
+    pub fn of(freqs: &[CpuFreq]) -> Option<FreqSummary> {
+        if freqs.is_empty() {
+            return None;
+        }
+        let total: u64 = freqs.iter().map(|f| f.current).sum();
+        let max = freqs.iter().map(|f| f.current).max().unwrap_or(0);
+        Some(FreqSummary {
+            average: total as f64 / freqs.len() as f64 / 1000.0,
+            max: max as f64 / 1000.0,
+        })
+    }
+}
+
+fn read_cpufreq(dir: &Path, core: usize) -> Option<CpuFreq> {
+    let dir = dir.join(format!("cpu{}/cpufreq", core));
+    let read = |file: &str| -> Option<String> {
+        fs::read_to_string(dir.join(file))
+            .ok()
+            .map(|content| content.trim().to_string())
+    };
+    let read_khz = |file: &str| -> Option<u64> { read(file)?.parse().ok() };
+    Some(CpuFreq {
+        core,
+        current: read_khz("scaling_cur_freq")?,
+        min: read_khz("scaling_min_freq").unwrap_or(0),
+        max: read_khz("scaling_max_freq").unwrap_or(0),
+        governor: read("scaling_governor"),
+        energy_performance_preference: read("energy_performance_preference"),
+    })
+}
+
+fn get_cpu_freqs_in(dir: &Path) -> Vec<CpuFreq> {
+    let mut cores: Vec<usize> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| {
+                let name = entry.ok()?.file_name();
+                name.to_str()?.strip_prefix("cpu")?.parse().ok()
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    cores.sort_unstable();
+    // Offline cores and machines without cpufreq have no `cpufreq` directory
+    cores
+        .into_iter()
+        .filter_map(|core| read_cpufreq(dir, core))
+        .collect()
+}
+
+/// Frequency of each online core, sorted by core number
+pub fn get_cpu_freqs() -> Vec<CpuFreq> {
+    get_cpu_freqs_in(Path::new(CPU_SYSFS_DIR))
+}
+
+/// Parse a CPU list such as `0-3,5,7-8`, return the number of CPUs in it
+fn count_cpu_list(list: &str) -> usize {
+    list.trim()
+        .split(',')
+        .filter(|range| !range.is_empty())
+        .map(|range| match range.split_once('-') {
+            Some((first, last)) => {
+                let first: usize = first.parse().expect("Read first CPU of range");
+                let last: usize = last.parse().expect("Read last CPU of range");
+                last - first + 1
+            }
+            None => 1,
+        })
+        .sum()
+}
+
+/// Number of online CPUs, from `/sys/devices/system/cpu/online`
+pub fn get_online_cpus() -> usize {
+    let path = Path::new(CPU_SYSFS_DIR).join("online");
+    let list = fs::read_to_string(&path)
+        .unwrap_or_else(|_| panic!("Open \"{}\" for reading", path.display()));
+    count_cpu_list(&list)
+}
+
+/// Content of `/proc/loadavg`
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct LoadAverage {
+    pub one: f64,
+    pub five: f64,
+    pub fifteen: f64,
+    /// Runnable tasks, i.e. processes and threads
+    pub running: u64,
+    pub total: u64,
+    pub last_pid: u64,
+    /// Number of online CPUs, to compare loads between machines
+    pub online_cpus: usize,
+}
+
+impl LoadAverage {
+    fn parse(line: &str, online_cpus: usize) -> LoadAverage {
         let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts[0] == "cpu" {
-            let user: u64 = parts[1].parse().expect("Read CPU `user` time");
-            let nice: u64 = parts[2].parse().expect("Read CPU `nice` time");
-            let system: u64 = parts[3].parse().expect("Read CPU `system` time");
-            let idle: u64 = parts[4].parse().expect("Read CPU `idle` time");
-            let iowait: u64 = parts[5].parse().expect("Read CPU `iowait` time");
-            let irq: u64 = parts[5].parse().expect("Read CPU `irq` time");
-            let softirq: u64 = parts[5].parse().expect("Read CPU `softirq` time");
-            let work_time = user + nice + system;
-            return CpuUsage {
-                work: work_time,
-                total: work_time + idle + iowait + irq + softirq,
-            };
+        let field = |index: usize, name: &str| -> &str {
+            parts
+                .get(index)
+                .unwrap_or_else(|| panic!("Read `{}` from /proc/loadavg", name))
+        };
+        let (running, total) = field(3, "tasks")
+            .split_once('/')
+            .expect("Read running/total tasks");
+        LoadAverage {
+            one: field(0, "1 minute load")
+                .parse()
+                .expect("Read 1 minute load"),
+            five: field(1, "5 minutes load")
+                .parse()
+                .expect("Read 5 minutes load"),
+            fifteen: field(2, "15 minutes load")
+                .parse()
+                .expect("Read 15 minutes load"),
+            running: running.parse().expect("Read running tasks"),
+            total: total.parse().expect("Read total tasks"),
+            last_pid: field(4, "last PID").parse().expect("Read last PID"),
+            online_cpus,
         }
     }
-    CpuUsage::default()
+
+    /// 1, 5 and 15 minutes load divided by the online CPUs, 100% means every CPU is busy
+    pub fn normalized(&self) -> [Percent; 3] {
+        let cpus = self.online_cpus.max(1) as f64;
+        [self.one, self.five, self.fifteen].map(|load| Percent::from(load, cpus))
+    }
+}
+
+pub fn get_load_average() -> LoadAverage {
+    let line = read_lines("/proc/loadavg")
+        .next()
+        .expect("Read /proc/loadavg");
+    LoadAverage::parse(&line, get_online_cpus())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROC_STAT: &str = "\
+cpu  100 10 50 800 20 5 15 0 30 0
+cpu0 60 5 25 390 10 3 7 0 30 0
+cpu2 40 5 25 410 10 2 8 0 0 0
+intr 123456 0 0
+ctxt 654321";
+
+    fn parse(content: &str) -> CpuSnapshot {
+        CpuSnapshot::parse(content.lines().map(String::from))
+    }
+
+    #[test]
+    fn every_column_is_read() {
+        let snapshot = parse(PROC_STAT);
+        assert_eq!(
+            snapshot.total,
+            CpuTimes {
+                user: 100,
+                nice: 10,
+                system: 50,
+                idle: 800,
+                iowait: 20,
+                irq: 5,
+                softirq: 15,
+                steal: 0,
+                guest: 30,
+                guest_nice: 0,
+            }
+        );
+        assert_eq!(snapshot.total.work(), 180);
+        assert_eq!(snapshot.total.total(), 1000);
+        // Offline cores leave a gap
+        assert_eq!(snapshot.cores.len(), 3);
+        assert_eq!(snapshot.cores[1], CpuTimes::default());
+        assert_eq!(snapshot.cores[2].softirq, 8);
+    }
+
+    #[test]
+    fn delta_gives_share_of_each_mode() {
+        let old = parse(PROC_STAT);
+        let new = parse(
+            "\
+cpu  150 10 100 1080 40 5 15 0 30 0
+cpu0 110 5 25 440 10 3 7 0 30 0",
+        );
+        let modes = new.total.delta(&old.total);
+        assert!((modes.user.value - 0.125).abs() < 1e-9);
+        assert!((modes.system.value - 0.125).abs() < 1e-9);
+        assert!((modes.idle.value - 0.7).abs() < 1e-9);
+        assert!((modes.iowait.value - 0.05).abs() < 1e-9);
+        assert!((modes.busy.value - 0.25).abs() < 1e-9);
+
+        let cores = new.core_deltas(&old);
+        assert_eq!(cores.len(), 1);
+        assert!((cores[0].busy.value - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn cpufreq_is_read_for_each_core() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        for (core, freq) in [(0, "1200000"), (1, "3600000")] {
+            let dir = root.join(format!("cpu{}/cpufreq", core));
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("scaling_cur_freq"), freq).unwrap();
+            fs::write(dir.join("scaling_min_freq"), "400000\n").unwrap();
+            fs::write(dir.join("scaling_max_freq"), "4800000\n").unwrap();
+            fs::write(dir.join("scaling_governor"), "powersave\n").unwrap();
+        }
+        // Neither a core nor online
+        fs::create_dir_all(root.join("cpufreq")).unwrap();
+        fs::create_dir_all(root.join("cpu2")).unwrap();
+
+        let freqs = get_cpu_freqs_in(root);
+        assert_eq!(freqs.len(), 2);
+        assert_eq!(freqs[1].current, 3_600_000);
+        assert_eq!(freqs[0].max, 4_800_000);
+        assert_eq!(freqs[0].governor.as_deref(), Some("powersave"));
+        assert_eq!(freqs[0].energy_performance_preference, None);
+        assert_eq!(
+            FreqSummary::of(&freqs),
+            Some(FreqSummary {
+                average: 2400.0,
+                max: 3600.0
+            })
+        );
+    }
+
+    #[test]
+    fn load_is_normalized_by_online_cpus() {
+        assert_eq!(count_cpu_list("0-3,6,8-9\n"), 7);
+        assert_eq!(count_cpu_list("0\n"), 1);
+
+        let load = LoadAverage::parse("2.00 1.00 0.50 3/812 41234\n", count_cpu_list("0-3"));
+        assert_eq!(load.running, 3);
+        assert_eq!(load.total, 812);
+        assert_eq!(load.last_pid, 41234);
+        let [one, five, fifteen] = load.normalized();
+        assert_eq!(one.value, 0.5);
+        assert_eq!(five.value, 0.25);
+        assert_eq!(fifteen.value, 0.125);
+    }
 }
@@ -0,0 +1,322 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::Mutex;
+use std::thread::sleep;
+use std::time::Duration;
+
+// DDC/CI lets us control external monitors over `/dev/i2c-*`, see the VESA
+// DDC/CI and MCCS standards, or ddcutil for a complete implementation.
+
+const DRM_DIR: &str = "/sys/class/drm";
+
+/// Monitors answer DDC/CI on this I2C address
+const DDC_ADDRESS: u16 = 0x37;
+/// `ioctl` request to choose the slave address, from `linux/i2c-dev.h`
+const I2C_SLAVE: libc::c_ulong = 0x0703;
+/// Address of the monitor as it appears in the checksum of requests
+const DESTINATION_ADDRESS: u8 = 0x6e;
+/// Address of the host as it appears in requests
+const SOURCE_ADDRESS: u8 = 0x51;
+/// Address of the host as it appears in the checksum of replies
+const REPLY_CHECKSUM_ADDRESS: u8 = 0x50;
+
+const GET_VCP_REQUEST: u8 = 0x01;
+const GET_VCP_REPLY: u8 = 0x02;
+const SET_VCP_REQUEST: u8 = 0x03;
+const GET_VCP_REPLY_LENGTH: usize = 11;
+
+/// Monitors need time to process a request before replying
+const REPLY_DELAY: Duration = Duration::from_millis(40);
+const SET_DELAY: Duration = Duration::from_millis(50);
+
+/// VCP feature code of the luminance (brightness)
+pub const VCP_LUMINANCE: u8 = 0x10;
+
+#[derive(thiserror::Error, Debug)]
+pub enum DdcError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid DDC/CI reply: {0:02x?}")]
+    InvalidReply(Vec<u8>),
+    #[error("Checksum mismatch in DDC/CI reply")]
+    Checksum,
+    #[error("VCP feature {0:#04x} is not supported")]
+    Unsupported(u8),
+}
+
+fn checksum(initial: u8, bytes: &[u8]) -> u8 {
+    bytes.iter().fold(initial, |acc, b| acc ^ b)
+}
+
+/// Wrap a payload into a DDC/CI message: source address, length, payload, checksum
+fn make_request(payload: &[u8]) -> Vec<u8> {
+    let mut message = vec![SOURCE_ADDRESS, 0x80 | payload.len() as u8];
+    message.extend_from_slice(payload);
+    message.push(checksum(DESTINATION_ADDRESS, &message));
+    message
+}
+
+/// Parse a Get VCP Feature reply, return (current, max)
+fn parse_get_vcp_reply(code: u8, reply: &[u8]) -> Result<(u16, u16), DdcError> {
+    let invalid = || DdcError::InvalidReply(reply.to_vec());
+    if reply.len() < GET_VCP_REPLY_LENGTH {
+        return Err(invalid());
+    }
+    let reply = &reply[..GET_VCP_REPLY_LENGTH];
+    let (message, expected) = reply.split_at(GET_VCP_REPLY_LENGTH - 1);
+    if checksum(REPLY_CHECKSUM_ADDRESS, message) != expected[0] {
+        return Err(DdcError::Checksum);
+    }
+    if reply[1] != 0x88 || reply[2] != GET_VCP_REPLY || reply[4] != code {
+        return Err(invalid());
+    }
+    if reply[3] != 0 {
+        return Err(DdcError::Unsupported(code));
+    }
+    let max = u16::from_be_bytes([reply[6], reply[7]]);
+    let current = u16::from_be_bytes([reply[8], reply[9]]);
+    Ok((current, max))
+}
+
+/// A monitor reachable through DDC/CI
+#[derive(Debug)]
+pub struct DdcDisplay<D = File> {
+    /// Name of the I2C bus, e.g. `i2c-5`
+    pub name: String,
+    device: Mutex<D>,
+}
+
+impl DdcDisplay<File> {
+    /// Open an I2C bus, e.g. `/dev/i2c-5`. This doesn't check whether a monitor is there.
+    pub fn open(path: &Path) -> Result<Self, DdcError> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        // SAFETY: I2C_SLAVE takes the address as an integer argument
+        if unsafe { libc::ioctl(file.as_raw_fd(), I2C_SLAVE, DDC_ADDRESS as libc::c_ulong) } < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Ok(DdcDisplay::with_device(name, file))
+    }
+
+    /// Probe the I2C buses of connected monitors and keep the ones where a monitor
+    /// reports its brightness. Buses without permission (see the `i2c` group) are skipped.
+    pub fn list() -> Vec<DdcDisplay> {
+        display_buses_in(Path::new(DRM_DIR))
+            .iter()
+            .filter_map(|bus| DdcDisplay::open(&Path::new("/dev").join(bus)).ok())
+            .filter(|display| display.get_vcp(VCP_LUMINANCE).is_ok())
+            .collect()
+    }
+}
+
+/// Names of the I2C buses wired to a connected monitor, e.g. `i2c-5`. Like ddcutil,
+/// only the buses DRM connectors point to are used, writing to the other buses could
+/// confuse touchpads, sensors or EEPROMs. Connectors link to their bus through `ddc`,
+/// DisplayPort connectors may instead have their AUX channel bus as a child.
+fn display_buses_in(drm_dir: &Path) -> Vec<String> {
+    let connectors = match fs::read_dir(drm_dir) {
+        Ok(entries) => entries.filter_map(|entry| Some(entry.ok()?.path())),
+        Err(_) => return Vec::new(),
+    };
+    let mut buses: Vec<String> = connectors
+        .filter(|path| {
+            fs::read_to_string(path.join("status")).is_ok_and(|status| status.trim() == "connected")
+        })
+        .filter_map(|path| {
+            if let Ok(target) = fs::read_link(path.join("ddc")) {
+                return Some(target.file_name()?.to_string_lossy().into_owned());
+            }
+            fs::read_dir(&path)
+                .ok()?
+                .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+                .find(|name| name.starts_with("i2c-"))
+        })
+        .collect();
+    buses.sort();
+    buses.dedup();
+    buses
+}
+
+impl<D: Read + Write> DdcDisplay<D> {
+    /// Talk to a monitor through anything that behaves like an I2C device
+    pub fn with_device(name: String, device: D) -> Self {
+        DdcDisplay {
+            name,
+            device: Mutex::new(device),
+        }
+    }
+
+    /// Read a VCP feature, return (current, max)
+    pub fn get_vcp(&self, code: u8) -> Result<(u16, u16), DdcError> {
+        let mut device = self.device.lock().unwrap();
+        device.write_all(&make_request(&[GET_VCP_REQUEST, code]))?;
+        sleep(REPLY_DELAY);
+        let mut reply = [0u8; GET_VCP_REPLY_LENGTH];
+        device.read_exact(&mut reply)?;
+        parse_get_vcp_reply(code, &reply)
+    }
+
+    pub fn set_vcp(&self, code: u8, value: u16) -> Result<(), DdcError> {
+        let mut device = self.device.lock().unwrap();
+        let [high, low] = value.to_be_bytes();
+        device.write_all(&make_request(&[SET_VCP_REQUEST, code, high, low]))?;
+        // The monitor ignores requests coming too fast after a set
+        sleep(SET_DELAY);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Behave like a monitor on the other end of an I2C bus
+    pub(crate) struct FakeMonitor {
+        pub luminance: u16,
+        pub max: u16,
+        reply: Vec<u8>,
+    }
+
+    impl FakeMonitor {
+        pub fn new(luminance: u16, max: u16) -> Self {
+            FakeMonitor {
+                luminance,
+                max,
+                reply: Vec::new(),
+            }
+        }
+    }
+
+    impl Write for FakeMonitor {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            assert_eq!(buf[0], SOURCE_ADDRESS);
+            assert_eq!(
+                checksum(DESTINATION_ADDRESS, &buf[..buf.len() - 1]),
+                buf[buf.len() - 1]
+            );
+            match &buf[2..buf.len() - 1] {
+                [GET_VCP_REQUEST, code] => {
+                    let supported = *code == VCP_LUMINANCE;
+                    let [max_high, max_low] = self.max.to_be_bytes();
+                    let [high, low] = self.luminance.to_be_bytes();
+                    let mut reply = vec![
+                        DESTINATION_ADDRESS,
+                        0x88,
+                        GET_VCP_REPLY,
+                        if supported { 0 } else { 1 },
+                        *code,
+                        0,
+                        max_high,
+                        max_low,
+                        high,
+                        low,
+                    ];
+                    reply.push(checksum(REPLY_CHECKSUM_ADDRESS, &reply));
+                    self.reply = reply;
+                }
+                [SET_VCP_REQUEST, VCP_LUMINANCE, high, low] => {
+                    self.luminance = u16::from_be_bytes([*high, *low])
+                }
+                request => panic!("Unexpected request {:02x?}", request),
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Read for FakeMonitor {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let len = buf.len().min(self.reply.len());
+            buf[..len].copy_from_slice(&self.reply[..len]);
+            self.reply.drain(..len);
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn only_buses_of_connected_monitors_are_probed() {
+        use std::os::unix::fs::symlink;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let connector = |name: &str, status: &str| {
+            let dir = root.join(name);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("status"), format!("{}\n", status)).unwrap();
+            dir
+        };
+        let hdmi = connector("card0-HDMI-A-1", "connected");
+        symlink("../../../0000:00:02.0/i2c-5", hdmi.join("ddc")).unwrap();
+        let dp = connector("card0-DP-1", "connected");
+        fs::create_dir(dp.join("i2c-7")).unwrap();
+        let unplugged = connector("card0-DP-2", "disconnected");
+        symlink("../../../0000:00:02.0/i2c-8", unplugged.join("ddc")).unwrap();
+        fs::create_dir(root.join("card0")).unwrap();
+
+        assert_eq!(display_buses_in(root), ["i2c-5", "i2c-7"]);
+    }
+
+    #[test]
+    fn request_has_length_and_checksum() {
+        // Example from the DDC/CI standard: Get VCP Feature of the brightness
+        assert_eq!(
+            make_request(&[GET_VCP_REQUEST, VCP_LUMINANCE]),
+            [0x51, 0x82, 0x01, 0x10, 0xac]
+        );
+    }
+
+    #[test]
+    fn luminance_is_read_and_written() {
+        let display = DdcDisplay::with_device("i2c-5".to_string(), FakeMonitor::new(30, 100));
+        assert_eq!(display.get_vcp(VCP_LUMINANCE).unwrap(), (30, 100));
+        display.set_vcp(VCP_LUMINANCE, 75).unwrap();
+        assert_eq!(display.get_vcp(VCP_LUMINANCE).unwrap(), (75, 100));
+        assert!(matches!(
+            display.get_vcp(0x12),
+            Err(DdcError::Unsupported(0x12))
+        ));
+    }
+
+    #[test]
+    fn corrupted_reply_is_rejected() {
+        let mut reply = vec![0x6e, 0x88, 0x02, 0x00, 0x10, 0x00, 0x00, 0x64, 0x00, 0x1e];
+        reply.push(checksum(REPLY_CHECKSUM_ADDRESS, &reply) ^ 1);
+        assert!(matches!(
+            parse_get_vcp_reply(VCP_LUMINANCE, &reply),
+            Err(DdcError::Checksum)
+        ));
+        assert!(matches!(
+            parse_get_vcp_reply(VCP_LUMINANCE, &[0x6e, 0x80, 0xbe]),
+            Err(DdcError::InvalidReply(_))
+        ));
+    }
+
+    #[test]
+    fn reply_is_read_from_device_file() {
+        // The request overwrites the first bytes, the reply follows right after
+        let request_len = make_request(&[GET_VCP_REQUEST, VCP_LUMINANCE]).len();
+        let mut content = vec![0; request_len];
+        let mut reply = vec![0x6e, 0x88, 0x02, 0x00, 0x10, 0x00, 0x00, 0x64, 0x00, 0x1e];
+        reply.push(checksum(REPLY_CHECKSUM_ADDRESS, &reply));
+        content.extend(reply);
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("i2c-0");
+        fs::write(&path, content).unwrap();
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        let display = DdcDisplay::with_device("i2c-0".to_string(), file);
+        assert_eq!(display.get_vcp(VCP_LUMINANCE).unwrap(), (30, 100));
+    }
+}
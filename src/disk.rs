@@ -1,6 +1,8 @@
 use std::fs;
+use std::str::FromStr;
+use std::time::Duration;
 
-use crate::util;
+use crate::util::{self, Percent};
 
 #[derive(Debug, Default, Copy, Clone)]
 pub struct DiskIo {
@@ -8,14 +10,271 @@ pub struct DiskIo {
     pub write: u64,
 }
 
+impl DiskIo {
+    /// Bytes read and written by all the devices combined
+    pub fn sum(stats: &[DiskStats]) -> DiskIo {
+        DiskIo {
+            read: stats.iter().map(|disk| disk.read_sectors).sum::<u64>() * SECTOR_SIZE,
+            write: stats.iter().map(|disk| disk.write_sectors).sum::<u64>() * SECTOR_SIZE,
+        }
+    }
+
+    /// Bytes read and written between two snapshots, by the devices present in both.
+    /// A device plugged in meanwhile would count all its past IO, and one unplugged
+    /// would make the total go backwards.
+    pub fn between(new: &[DiskStats], old: &[DiskStats]) -> DiskIo {
+        let mut result = DiskIo::default();
+        for disk in new {
+            if let Some(old) = old.iter().find(|old| old.name == disk.name) {
+                result.read += disk.read_sectors.saturating_sub(old.read_sectors) * SECTOR_SIZE;
+                result.write += disk.write_sectors.saturating_sub(old.write_sectors) * SECTOR_SIZE;
+            }
+        }
+        result
+    }
+}
+
+// Columns of /proc/diskstats, see https://docs.kernel.org/admin-guide/iostats.html
+// Discard columns were added in 4.18 and flush columns in 5.5
 const DEVICE_NAME: usize = 2;
+const READS: usize = 3;
 const READ_SECTORS: usize = 5;
+const READ_TIME: usize = 6;
+const WRITES: usize = 7;
 const WRITE_SECTORS: usize = 9;
+const WRITE_TIME: usize = 10;
+const IN_FLIGHT: usize = 11;
+const IO_TICKS: usize = 12;
+const TIME_IN_QUEUE: usize = 13;
+const DISCARDS: usize = 14;
+const DISCARD_SECTORS: usize = 16;
+const DISCARD_TIME: usize = 17;
+const FLUSHES: usize = 18;
+const FLUSH_TIME: usize = 19;
 // Sector is not device-specific, it is the standard UNIX 512 bytes sector
 // https://www.kernel.org/doc/Documentation/block/stat.txt
-const SECTOR_SIZE: usize = 512;
+const SECTOR_SIZE: u64 = 512;
 
-pub fn get_disk_io() -> DiskIo {
+/// Kind of block device, guessed from its name
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DeviceClass {
+    Loop,
+    Ram,
+    Zram,
+    /// Device mapper, e.g. LVM or LUKS, on top of other devices
+    Dm,
+    /// Software RAID, on top of other devices
+    Md,
+    /// Anything else, usually a physical disk
+    Other,
+}
+
+impl DeviceClass {
+    pub fn of(name: &str) -> DeviceClass {
+        if name.starts_with("loop") {
+            DeviceClass::Loop
+        } else if name.starts_with("zram") {
+            DeviceClass::Zram
+        } else if name.starts_with("ram") {
+            DeviceClass::Ram
+        } else if name.starts_with("dm-") {
+            DeviceClass::Dm
+        } else if name.starts_with("md") {
+            DeviceClass::Md
+        } else {
+            DeviceClass::Other
+        }
+    }
+}
+
+impl FromStr for DeviceClass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "loop" => Ok(DeviceClass::Loop),
+            "ram" => Ok(DeviceClass::Ram),
+            "zram" => Ok(DeviceClass::Zram),
+            "dm" => Ok(DeviceClass::Dm),
+            "md" => Ok(DeviceClass::Md),
+            _ => Err(format!("Unknown device class {}", s)),
+        }
+    }
+}
+
+/// Which devices to count. A device whose class is included is always kept,
+/// otherwise it is kept unless its class is excluded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiskFilter {
+    pub include: Vec<DeviceClass>,
+    pub exclude: Vec<DeviceClass>,
+}
+
+impl DiskFilter {
+    pub fn matches(&self, name: &str) -> bool {
+        let class = DeviceClass::of(name);
+        self.include.contains(&class) || !self.exclude.contains(&class)
+    }
+
+    /// Parse a comma separated list of classes, e.g. `loop,zram`
+    pub fn parse_classes(list: &str) -> Result<Vec<DeviceClass>, String> {
+        list.split(',').map(|class| class.trim().parse()).collect()
+    }
+}
+
+impl Default for DiskFilter {
+    /// Only physical disks, virtual devices either live in memory or would
+    /// count the IO of the disks below them a second time
+    fn default() -> Self {
+        DiskFilter {
+            include: Vec::new(),
+            exclude: vec![
+                DeviceClass::Loop,
+                DeviceClass::Ram,
+                DeviceClass::Zram,
+                DeviceClass::Dm,
+                DeviceClass::Md,
+            ],
+        }
+    }
+}
+
+/// Counters of a block device from `/proc/diskstats`, times are in milliseconds
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DiskStats {
+    pub name: String,
+    pub reads: u64,
+    pub read_sectors: u64,
+    pub read_time: u64,
+    pub writes: u64,
+    pub write_sectors: u64,
+    pub write_time: u64,
+    /// Requests currently being processed
+    pub in_flight: u64,
+    /// Time the device had at least one request in flight
+    pub io_ticks: u64,
+    /// Time spent by all requests, weighted by the number of requests in flight
+    pub time_in_queue: u64,
+    pub discards: u64,
+    pub discard_sectors: u64,
+    pub discard_time: u64,
+    pub flushes: u64,
+    pub flush_time: u64,
+}
+
+impl DiskStats {
+    fn parse(line: &str) -> DiskStats {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        // Columns missing on older kernels are left at 0
+        let column = |index: usize| -> u64 {
+            parts.get(index).map_or(0, |value| {
+                value
+                    .parse()
+                    .unwrap_or_else(|_| panic!("Read column {} of disk stats", index))
+            })
+        };
+        DiskStats {
+            name: parts
+                .get(DEVICE_NAME)
+                .expect("Get device name from disk stats")
+                .to_string(),
+            reads: column(READS),
+            read_sectors: column(READ_SECTORS),
+            read_time: column(READ_TIME),
+            writes: column(WRITES),
+            write_sectors: column(WRITE_SECTORS),
+            write_time: column(WRITE_TIME),
+            in_flight: column(IN_FLIGHT),
+            io_ticks: column(IO_TICKS),
+            time_in_queue: column(TIME_IN_QUEUE),
+            discards: column(DISCARDS),
+            discard_sectors: column(DISCARD_SECTORS),
+            discard_time: column(DISCARD_TIME),
+            flushes: column(FLUSHES),
+            flush_time: column(FLUSH_TIME),
+        }
+    }
+}
+
+/// How hard a device worked between two [`DiskStats`], like the `%util`,
+/// `await` and `aqu-sz` columns of `iostat -x`
+#[derive(Debug, Default, Clone)]
+pub struct DiskUtilization {
+    pub name: String,
+    /// Share of time the device had requests in flight. This is 100% for a saturated
+    /// HDD, but devices serving requests in parallel like SSDs can take more load.
+    pub busy: Percent,
+    /// Average time in milliseconds requests took to complete, waiting in the queue included
+    pub await_time: f64,
+    /// Average number of requests in the queue
+    pub queue_size: f64,
+}
+
+impl DiskStats {
+    fn requests(&self) -> u64 {
+        self.reads + self.writes + self.discards + self.flushes
+    }
+
+    fn request_time(&self) -> u64 {
+        self.read_time + self.write_time + self.discard_time + self.flush_time
+    }
+
+    /// Compare with an older snapshot of the same device, taken `elapsed` ago
+    pub fn utilization(&self, old: &DiskStats, elapsed: Duration) -> DiskUtilization {
+        let elapsed = elapsed.as_secs_f64() * 1000.0;
+        let requests = self.requests().saturating_sub(old.requests());
+        let request_time = self.request_time().saturating_sub(old.request_time());
+        let io_ticks = self.io_ticks.saturating_sub(old.io_ticks) as f64;
+        let time_in_queue = self.time_in_queue.saturating_sub(old.time_in_queue) as f64;
+        if elapsed == 0.0 {
+            return DiskUtilization {
+                name: self.name.clone(),
+                ..DiskUtilization::default()
+            };
+        }
+        DiskUtilization {
+            name: self.name.clone(),
+            busy: Percent::from_normalized((io_ticks / elapsed).min(1.0)),
+            await_time: if requests == 0 {
+                0.0
+            } else {
+                request_time as f64 / requests as f64
+            },
+            queue_size: time_in_queue / elapsed,
+        }
+    }
+}
+
+/// Utilization of each device present in both snapshots, sorted from the busiest
+pub fn disk_utilizations(
+    new: &[DiskStats],
+    old: &[DiskStats],
+    elapsed: Duration,
+) -> Vec<DiskUtilization> {
+    let mut result: Vec<DiskUtilization> = new
+        .iter()
+        .filter_map(|disk| {
+            let old = old.iter().find(|old| old.name == disk.name)?;
+            Some(disk.utilization(old, elapsed))
+        })
+        .collect();
+    result.sort_by(|a, b| b.busy.value.total_cmp(&a.busy.value));
+    result
+}
+
+/// Keep whole devices (not partitions) accepted by the filter
+fn parse_disk_stats(
+    lines: impl Iterator<Item = String>,
+    drives: &[String],
+    filter: &DiskFilter,
+) -> Vec<DiskStats> {
+    lines
+        .map(|line| DiskStats::parse(&line))
+        .filter(|stats| drives.contains(&stats.name) && filter.matches(&stats.name))
+        .collect()
+}
+
+pub fn get_disk_stats(filter: &DiskFilter) -> Vec<DiskStats> {
     let drives: Vec<String> = fs::read_dir("/sys/block")
         .expect("Read directory /sys/block")
         .map(|entry| {
@@ -26,32 +285,109 @@ pub fn get_disk_io() -> DiskIo {
                 .expect("Convert OsString to String")
         })
         .collect();
-    let mut read: usize = 0;
-    let mut write: usize = 0;
-    for line in util::read_lines("/proc/diskstats") {
-        let device_name = line
-            .split_whitespace()
-            .nth(DEVICE_NAME)
-            .expect("Get device name from disk stats");
-        if drives.contains(&device_name.to_string()) {
-            read += SECTOR_SIZE
-                * line
-                    .split_whitespace()
-                    .nth(READ_SECTORS)
-                    .expect("Get read sectors from disk stats")
-                    .parse::<usize>()
-                    .unwrap();
-            write += SECTOR_SIZE
-                * line
-                    .split_whitespace()
-                    .nth(WRITE_SECTORS)
-                    .expect("Get write sectors from disk stats")
-                    .parse::<usize>()
-                    .unwrap();
-        }
-    }
-    DiskIo {
-        read: read as u64,
-        write: write as u64,
+    parse_disk_stats(util::read_lines("/proc/diskstats"), &drives, filter)
+}
+
+/// Bytes read and written by physical disks
+pub fn get_disk_io() -> DiskIo {
+    DiskIo::sum(&get_disk_stats(&DiskFilter::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DISKSTATS: &str = "\
+   7       0 loop0 1204 0 51012 312 0 0 0 0 0 420 312 0 0 0 0 0 0
+ 259       0 nvme0n1 183465 41036 13027370 41233 361102 214575 20874978 274112 2 216308 337544 9315 0 203947376 2171 22483 20027
+ 259       1 nvme0n1p1 410 1500 30458 102 2 0 2 0 0 97 103 0 0 0 0 0 0
+   8       0 sda 1000 0 8000 500 2000 0 16000 1500 0 1800 2000
+ 252       0 zram0 300 0 2400 1 40000 0 320000 45 0 60 46 0 0 0 0 0 0
+ 254       0 dm-0 180000 0 13000000 45000 570000 0 20800000 610000 0 220000 655000 9300 0 203000000 2100 0 0";
+
+    fn parse(filter: &DiskFilter) -> Vec<DiskStats> {
+        let drives: Vec<String> = ["loop0", "nvme0n1", "sda", "zram0", "dm-0"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        parse_disk_stats(DISKSTATS.lines().map(String::from), &drives, filter)
+    }
+
+    #[test]
+    fn every_column_is_read() {
+        let stats = parse(&DiskFilter::default());
+        let names: Vec<&str> = stats.iter().map(|disk| disk.name.as_str()).collect();
+        assert_eq!(names, ["nvme0n1", "sda"]);
+        let nvme = &stats[0];
+        assert_eq!(nvme.reads, 183465);
+        assert_eq!(nvme.write_sectors, 20874978);
+        assert_eq!(nvme.in_flight, 2);
+        assert_eq!(nvme.io_ticks, 216308);
+        assert_eq!(nvme.time_in_queue, 337544);
+        assert_eq!(nvme.discards, 9315);
+        assert_eq!(nvme.discard_sectors, 203947376);
+        assert_eq!(nvme.flushes, 22483);
+        // An old kernel without discard and flush columns
+        assert_eq!(stats[1].io_ticks, 1800);
+        assert_eq!(stats[1].discards, 0);
+
+        let io = DiskIo::sum(&stats);
+        assert_eq!(io.read, (13027370 + 8000) * 512);
+        assert_eq!(io.write, (20874978 + 16000) * 512);
+    }
+
+    #[test]
+    fn devices_are_filtered_by_class() {
+        let filter = DiskFilter {
+            include: DiskFilter::parse_classes("dm").unwrap(),
+            ..DiskFilter::default()
+        };
+        let names: Vec<String> = parse(&filter).into_iter().map(|disk| disk.name).collect();
+        assert_eq!(names, ["nvme0n1", "sda", "dm-0"]);
+        let filter = DiskFilter {
+            include: Vec::new(),
+            exclude: DiskFilter::parse_classes("loop, dm").unwrap(),
+        };
+        let names: Vec<String> = parse(&filter).into_iter().map(|disk| disk.name).collect();
+        assert_eq!(names, ["nvme0n1", "sda", "zram0"]);
+        assert_eq!(DeviceClass::of("md127"), DeviceClass::Md);
+        assert_eq!(DeviceClass::of("mmcblk0"), DeviceClass::Other);
+        assert!(DiskFilter::parse_classes("loop,floppy").is_err());
+    }
+
+    #[test]
+    fn io_is_only_counted_for_devices_in_both_snapshots() {
+        let old = parse(&DiskFilter::default());
+        let mut new = old.clone();
+        new[0].read_sectors += 10;
+        new[0].write_sectors += 20;
+        // sda was unplugged and sdb plugged in
+        new[1].name = "sdb".to_string();
+        let io = DiskIo::between(&new, &old);
+        assert_eq!((io.read, io.write), (10 * 512, 20 * 512));
+    }
+
+    #[test]
+    fn busiest_device_comes_first() {
+        let old = parse(&DiskFilter::default());
+        let mut new = old.clone();
+        // 870ms busy out of 1s, 60 requests taking 1200ms
+        new[1].io_ticks += 870;
+        new[1].reads += 40;
+        new[1].writes += 20;
+        new[1].read_time += 400;
+        new[1].write_time += 800;
+        new[1].time_in_queue += 1500;
+        new[0].io_ticks += 100;
+        new[0].writes += 10;
+        new[0].write_time += 5;
+
+        let disks = disk_utilizations(&new, &old, Duration::from_secs(1));
+        assert_eq!(disks[0].name, "sda");
+        assert!((disks[0].busy.value - 0.87).abs() < 1e-9);
+        assert_eq!(disks[0].await_time, 20.0);
+        assert_eq!(disks[0].queue_size, 1.5);
+        assert_eq!(disks[1].name, "nvme0n1");
+        assert_eq!(disks[1].await_time, 0.5);
     }
 }
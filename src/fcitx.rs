@@ -1,8 +1,14 @@
 use core::convert::TryFrom;
+use futures_util::StreamExt;
 use serde::Serialize;
-use zbus::dbus_proxy;
+use zbus::fdo::MonitoringProxy;
+use zbus::{dbus_proxy, Connection, MatchRule, MessageStream, MessageType, OwnedMatchRule};
 use zvariant::{Signature, Type};
 
+/// An entry of `AvailableInputMethods`: unique name, display name, description,
+/// icon, label, language code, and whether it is configurable
+pub type AvailableInputMethod = (String, String, String, String, String, String, bool);
+
 #[derive(Debug, Clone, Default, Serialize, zvariant::OwnedValue)]
 pub struct InputMethod {
     /// The name displayed on the UI
@@ -24,8 +30,8 @@ impl From<(String, String, String, bool)> for InputMethod {
         }
     }
 }
-impl From<(String, String, String, String, String, String, bool)> for InputMethod {
-    fn from(tup: (String, String, String, String, String, String, bool)) -> InputMethod {
+impl From<AvailableInputMethod> for InputMethod {
+    fn from(tup: AvailableInputMethod) -> InputMethod {
         Self {
             display_name: tup.1,
             name: tup.0,
@@ -51,12 +57,330 @@ pub trait Fcitx5Controller {
     #[dbus_proxy(name = "CurrentInputMethodGroup")]
     fn current_input_method_group(&self) -> zbus::Result<String>;
     #[dbus_proxy(name = "AvailableInputMethods")]
-    fn input_methods(
-        &self,
-    ) -> zbus::Result<Vec<(String, String, String, String, String, String, bool)>>;
+    fn input_methods(&self) -> zbus::Result<Vec<AvailableInputMethod>>;
     #[dbus_proxy(name = "InputMethodGroupInfo")]
     fn input_method_group_info(
         &self,
         group_name: &str,
     ) -> zbus::Result<(String, Vec<(String, String)>)>;
+    #[dbus_proxy(name = "InputMethodGroups")]
+    fn input_method_groups(&self) -> zbus::Result<Vec<String>>;
+    /// Switch to an IM by its internal name, e.g. `keyboard-us`
+    #[dbus_proxy(name = "SetCurrentIM")]
+    fn set_current_input_method(&self, name: &str) -> zbus::Result<()>;
+    #[dbus_proxy(name = "SwitchInputMethodGroup")]
+    fn switch_input_method_group(&self, group_name: &str) -> zbus::Result<()>;
+    /// Toggle between the first IM of the group and the last active one
+    #[dbus_proxy(name = "Toggle")]
+    fn toggle(&self) -> zbus::Result<()>;
+    #[dbus_proxy(name = "Activate")]
+    fn activate(&self) -> zbus::Result<()>;
+    #[dbus_proxy(name = "Deactivate")]
+    fn deactivate(&self) -> zbus::Result<()>;
+    /// Reload the configuration files
+    #[dbus_proxy(name = "ReloadConfig")]
+    fn reload(&self) -> zbus::Result<()>;
+    /// Groups were edited, or the current group was switched
+    #[dbus_proxy(signal, name = "InputMethodGroupsChanged")]
+    fn input_method_groups_changed(&self) -> zbus::Result<()>;
+}
+
+/// The name `step` positions away from `current`, wrapping around.
+/// Starts from the first name when `current` isn't in the list.
+pub fn cycle<'a>(names: &'a [String], current: &str, step: isize) -> Option<&'a str> {
+    if names.is_empty() {
+        return None;
+    }
+    let index = match names.iter().position(|name| name == current) {
+        Some(index) => (index as isize + step).rem_euclid(names.len() as isize) as usize,
+        None => 0,
+    };
+    Some(&names[index])
+}
+
+/// Internal names of the IMs in the current group
+pub async fn current_group_input_methods(
+    proxy: &Fcitx5ControllerProxy<'_>,
+) -> zbus::Result<Vec<String>> {
+    let group = proxy.current_input_method_group().await?;
+    Ok(proxy
+        .input_method_group_info(&group)
+        .await?
+        .1
+        .into_iter()
+        .map(|(name, _layout)| name)
+        .collect())
+}
+
+/// Switch to the IM `step` positions away in the current group, return its
+/// internal name, or `None` if the group is empty
+pub async fn step_input_method(
+    proxy: &Fcitx5ControllerProxy<'_>,
+    step: isize,
+) -> zbus::Result<Option<String>> {
+    let names = current_group_input_methods(proxy).await?;
+    let current = proxy.current_input_method().await?;
+    match cycle(&names, &current, step) {
+        Some(name) => {
+            proxy.set_current_input_method(name).await?;
+            Ok(Some(name.to_string()))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Find an IM of the current group by its internal name, e.g. `keyboard-us`, or
+/// its display name, e.g. `Pinyin`. Return its internal name, `None` if not found.
+pub async fn find_input_method(
+    proxy: &Fcitx5ControllerProxy<'_>,
+    name: &str,
+) -> zbus::Result<Option<String>> {
+    let names = current_group_input_methods(proxy).await?;
+    if names.iter().any(|im| im == name) {
+        return Ok(Some(name.to_string()));
+    }
+    Ok(proxy
+        .input_methods()
+        .await?
+        .into_iter()
+        .map(InputMethod::from)
+        .find(|im| names.contains(&im.name) && im.display_name.eq_ignore_ascii_case(name))
+        .map(|im| im.name))
+}
+
+/// IM switches of every input context, as internal names.
+/// fcitx5 sends `CurrentIM` only to the application owning the input context,
+/// so a plain subscription never sees it: the session bus has to be monitored.
+pub struct InputMethodSwitches {
+    rule: OwnedMatchRule,
+    messages: MessageStream,
+}
+
+impl InputMethodSwitches {
+    fn rule() -> OwnedMatchRule {
+        // Input contexts live on their own object paths, so match on any path
+        MatchRule::builder()
+            .msg_type(MessageType::Signal)
+            .interface("org.fcitx.Fcitx.InputContext1")
+            .and_then(|builder| builder.member("CurrentIM"))
+            .expect("Valid match rule")
+            .build()
+            .into()
+    }
+
+    /// Receive every message of `connection`, keep the `CurrentIM` signals
+    fn from_connection(connection: &Connection) -> Self {
+        InputMethodSwitches {
+            rule: Self::rule(),
+            messages: MessageStream::from(connection),
+        }
+    }
+
+    /// Open a monitor connection to the session bus. Fails when the bus
+    /// policy doesn't allow us to monitor.
+    pub async fn monitor() -> zbus::Result<Self> {
+        let connection = Connection::session().await?;
+        // Subscribe before the bus starts forwarding, to not miss a switch
+        let switches = Self::from_connection(&connection);
+        MonitoringProxy::new(&connection)
+            .await?
+            .become_monitor(&[&switches.rule.to_string()], 0)
+            .await?;
+        Ok(switches)
+    }
+
+    /// Wait for the next switch, `None` when the connection is closed
+    pub async fn next(&mut self) -> Option<String> {
+        while let Some(message) = self.messages.next().await {
+            let message = match message {
+                Ok(message) if self.rule.matches(&message).unwrap_or(false) => message,
+                _ => continue,
+            };
+            // Name, internal name and language code
+            if let Ok((_, name, _)) = message.body::<(String, String, String)>() {
+                return Some(name);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixStream;
+    use zbus::{dbus_interface, ConnectionBuilder, Guid};
+
+    struct MockController {
+        current: String,
+        /// Internal names of the IMs in the only group
+        group: Vec<String>,
+        /// (internal name, display name) of every IM installed
+        available: Vec<(String, String)>,
+    }
+
+    #[dbus_interface(name = "org.fcitx.Fcitx.Controller1")]
+    impl MockController {
+        #[dbus_interface(name = "CurrentInputMethod")]
+        fn current_input_method(&self) -> String {
+            self.current.clone()
+        }
+
+        #[dbus_interface(name = "SetCurrentIM")]
+        fn set_current_input_method(&mut self, name: String) {
+            self.current = name;
+        }
+
+        #[dbus_interface(name = "CurrentInputMethodGroup")]
+        fn current_input_method_group(&self) -> String {
+            "Default".to_string()
+        }
+
+        #[dbus_interface(name = "InputMethodGroupInfo")]
+        fn input_method_group_info(&self, _group_name: String) -> (String, Vec<(String, String)>) {
+            let layouts = self
+                .group
+                .iter()
+                .map(|name| (name.clone(), String::new()))
+                .collect();
+            ("us".to_string(), layouts)
+        }
+
+        #[dbus_interface(name = "AvailableInputMethods")]
+        fn input_methods(&self) -> Vec<AvailableInputMethod> {
+            self.available
+                .iter()
+                .map(|(name, display_name)| {
+                    (
+                        name.clone(),
+                        display_name.clone(),
+                        String::new(),
+                        String::new(),
+                        String::new(),
+                        String::new(),
+                        true,
+                    )
+                })
+                .collect()
+        }
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    /// A fcitx5 service and a client talking over a private socket
+    async fn connect() -> (Connection, Connection) {
+        let (service_socket, client_socket) = UnixStream::pair().unwrap();
+        let guid = Guid::generate();
+        let service = ConnectionBuilder::unix_stream(service_socket)
+            .server(&guid)
+            .p2p()
+            .serve_at(
+                "/controller",
+                MockController {
+                    current: "keyboard-us".to_string(),
+                    group: strings(&["keyboard-us", "pinyin", "mozc"]),
+                    available: vec![
+                        (
+                            "keyboard-us".to_string(),
+                            "Keyboard - English (US)".to_string(),
+                        ),
+                        ("pinyin".to_string(), "Pinyin".to_string()),
+                        ("mozc".to_string(), "Mozc".to_string()),
+                        ("anthy".to_string(), "Anthy".to_string()),
+                    ],
+                },
+            )
+            .unwrap()
+            .build();
+        let client = ConnectionBuilder::unix_stream(client_socket).p2p().build();
+        let (service, client) = tokio::join!(service, client);
+        (service.unwrap(), client.unwrap())
+    }
+
+    #[tokio::test]
+    async fn group_changes_are_received_from_signals() {
+        let (service, client) = connect().await;
+        let proxy = Fcitx5ControllerProxy::new(&client).await.unwrap();
+        assert_eq!(proxy.current_input_method().await.unwrap(), "keyboard-us");
+
+        let mut groups_changed = proxy.receive_input_method_groups_changed().await.unwrap();
+        service
+            .emit_signal(
+                None::<()>,
+                "/controller",
+                "org.fcitx.Fcitx.Controller1",
+                "InputMethodGroupsChanged",
+                &(),
+            )
+            .await
+            .unwrap();
+        assert!(groups_changed.next().await.is_some());
+    }
+
+    #[tokio::test]
+    async fn input_method_switches_are_received_from_any_input_context() {
+        let (service, client) = connect().await;
+        let mut switches = InputMethodSwitches::from_connection(&client);
+        let emit = |path, interface, member| {
+            service.emit_signal(
+                None::<()>,
+                path,
+                interface,
+                member,
+                &("Pinyin", "pinyin", "zh_CN"),
+            )
+        };
+        // Not a switch, must be skipped
+        emit("/controller", "org.fcitx.Fcitx.Controller1", "CurrentIM")
+            .await
+            .unwrap();
+        emit(
+            "/org/freedesktop/portal/inputcontext/3",
+            "org.fcitx.Fcitx.InputContext1",
+            "CurrentIM",
+        )
+        .await
+        .unwrap();
+        assert_eq!(switches.next().await.as_deref(), Some("pinyin"));
+    }
+
+    #[tokio::test]
+    async fn input_methods_are_cycled_in_the_group() {
+        let (_service, client) = connect().await;
+        let proxy = Fcitx5ControllerProxy::new(&client).await.unwrap();
+        let mut visited = Vec::new();
+        for step in [1, 1, 1, -1, -1] {
+            visited.push(step_input_method(&proxy, step).await.unwrap().unwrap());
+        }
+        assert_eq!(visited, ["pinyin", "mozc", "keyboard-us", "mozc", "pinyin"]);
+        assert_eq!(proxy.current_input_method().await.unwrap(), "pinyin");
+    }
+
+    #[tokio::test]
+    async fn input_method_is_found_by_internal_or_display_name() {
+        let (_service, client) = connect().await;
+        let proxy = Fcitx5ControllerProxy::new(&client).await.unwrap();
+        let find = |name: &'static str| find_input_method(&proxy, name);
+        assert_eq!(find("mozc").await.unwrap().as_deref(), Some("mozc"));
+        assert_eq!(find("pinyin").await.unwrap().as_deref(), Some("pinyin"));
+        assert_eq!(
+            find("keyboard - english (us)").await.unwrap().as_deref(),
+            Some("keyboard-us")
+        );
+        // Installed but not in the group
+        assert_eq!(find("Anthy").await.unwrap(), None);
+        assert_eq!(find("klingon").await.unwrap(), None);
+    }
+
+    #[test]
+    fn cycle_wraps_around() {
+        let names = strings(&["keyboard-us", "pinyin", "mozc"]);
+        assert_eq!(cycle(&names, "pinyin", 1), Some("mozc"));
+        assert_eq!(cycle(&names, "mozc", 1), Some("keyboard-us"));
+        assert_eq!(cycle(&names, "keyboard-us", -1), Some("mozc"));
+        assert_eq!(cycle(&names, "unknown", 1), Some("keyboard-us"));
+        assert_eq!(cycle(&[], "pinyin", 1), None);
+    }
 }
@@ -0,0 +1,306 @@
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use crate::util::{read_lines, Percent};
+
+/// Filesystems without a backing store worth reporting. Squashfs is always full,
+/// e.g. snaps, and tmpfs is memory already shown elsewhere.
+const PSEUDO_FILESYSTEMS: [&str; 25] = [
+    "autofs",
+    "binfmt_misc",
+    "bpf",
+    "cgroup",
+    "cgroup2",
+    "configfs",
+    "debugfs",
+    "devpts",
+    "devtmpfs",
+    "efivarfs",
+    "fusectl",
+    "fuse.gvfsd-fuse",
+    "fuse.portal",
+    "hugetlbfs",
+    "mqueue",
+    "nsfs",
+    "proc",
+    "pstore",
+    "ramfs",
+    "rpc_pipefs",
+    "securityfs",
+    "squashfs",
+    "sysfs",
+    "tmpfs",
+    "tracefs",
+];
+
+/// `statvfs` on these waits for the server, and blocks when it is unreachable
+const NETWORK_FILESYSTEMS: [&str; 10] = [
+    "9p",
+    "ceph",
+    "cifs",
+    "fuse.rclone",
+    "fuse.sshfs",
+    "glusterfs",
+    "nfs",
+    "nfs4",
+    "smb3",
+    "smbfs",
+];
+
+/// Media that is full by design, even when mounted read-write
+const READ_ONLY_FILESYSTEMS: [&str; 3] = ["erofs", "iso9660", "udf"];
+
+/// A mount from `/proc/self/mountinfo`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mount {
+    /// `major:minor` of the device, shared by bind mounts and btrfs subvolumes
+    pub device: String,
+    pub mount_point: PathBuf,
+    pub fs_type: String,
+    /// E.g. `/dev/nvme0n1p2`
+    pub source: String,
+    /// Mounted with `ro`
+    pub read_only: bool,
+}
+
+impl Mount {
+    /// Parse a line such as
+    /// `36 35 98:0 / /mnt rw,noatime master:1 - ext4 /dev/sda1 rw,errors=continue`,
+    /// see proc(5). The number of optional fields before `-` varies.
+    fn parse(line: &str) -> Option<Mount> {
+        let (before, after) = line.split_once(" - ")?;
+        let before: Vec<&str> = before.split_whitespace().collect();
+        let mut after = after.split_whitespace();
+        Some(Mount {
+            device: before.get(2)?.to_string(),
+            mount_point: PathBuf::from(unescape(before.get(4)?)),
+            read_only: before.get(5)?.split(',').any(|option| option == "ro"),
+            fs_type: after.next()?.to_string(),
+            source: unescape(after.next()?),
+        })
+    }
+
+    pub fn is_pseudo(&self) -> bool {
+        PSEUDO_FILESYSTEMS.contains(&self.fs_type.as_str())
+    }
+
+    pub fn is_network(&self) -> bool {
+        NETWORK_FILESYSTEMS.contains(&self.fs_type.as_str())
+    }
+
+    /// Nothing can be written, so it can't fill up
+    pub fn is_read_only(&self) -> bool {
+        self.read_only || READ_ONLY_FILESYSTEMS.contains(&self.fs_type.as_str())
+    }
+}
+
+/// Decode the octal escapes of spaces, tabs, newlines and backslashes, e.g. `\040`
+fn unescape(field: &str) -> String {
+    let mut result = String::with_capacity(field.len());
+    let mut rest = field;
+    while let Some(index) = rest.find('\\') {
+        result.push_str(&rest[..index]);
+        let code = rest.get(index + 1..index + 4);
+        match code.and_then(|code| u8::from_str_radix(code, 8).ok()) {
+            Some(byte) => {
+                result.push(byte as char);
+                rest = &rest[index + 4..];
+            }
+            None => {
+                result.push('\\');
+                rest = &rest[index + 1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Real filesystems, only the first mount of each device is kept
+fn parse_mountinfo(lines: impl Iterator<Item = String>) -> Vec<Mount> {
+    let mut mounts: Vec<Mount> = Vec::new();
+    for mount in lines.filter_map(|line| Mount::parse(&line)) {
+        if !mount.is_pseudo() && !mounts.iter().any(|other| other.device == mount.device) {
+            mounts.push(mount);
+        }
+    }
+    mounts
+}
+
+pub fn get_mounts() -> Vec<Mount> {
+    parse_mountinfo(read_lines("/proc/self/mountinfo"))
+}
+
+/// Space and inode usage of a filesystem, sizes are in bytes
+#[derive(Debug, Clone)]
+pub struct FilesystemUsage {
+    pub mount: Mount,
+    pub total: u64,
+    pub used: u64,
+    /// Available to unprivileged users, blocks reserved for root are excluded
+    pub avail: u64,
+    pub inodes_total: u64,
+    pub inodes_used: u64,
+}
+
+impl FilesystemUsage {
+    /// Like `df`, the reserved blocks count as neither used nor available
+    pub fn usage(&self) -> Percent {
+        let usable = self.used + self.avail;
+        if usable == 0 {
+            Percent::default()
+        } else {
+            Percent::from(self.used as f64, usable as f64)
+        }
+    }
+
+    /// Some filesystems like btrfs have no fixed number of inodes, those are at 0%
+    pub fn inode_usage(&self) -> Percent {
+        if self.inodes_total == 0 {
+            Percent::default()
+        } else {
+            Percent::from(self.inodes_used as f64, self.inodes_total as f64)
+        }
+    }
+}
+
+fn statvfs(path: &Path) -> io::Result<libc::statvfs> {
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    // SAFETY: statvfs is plain old data, and the call only writes into it
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stat)
+}
+
+pub fn get_filesystem_usage(mount: Mount) -> io::Result<FilesystemUsage> {
+    let stat = statvfs(&mount.mount_point)?;
+    let block_size = stat.f_frsize as u64;
+    Ok(FilesystemUsage {
+        total: stat.f_blocks as u64 * block_size,
+        used: (stat.f_blocks as u64).saturating_sub(stat.f_bfree as u64) * block_size,
+        avail: stat.f_bavail as u64 * block_size,
+        inodes_total: stat.f_files as u64,
+        inodes_used: (stat.f_files as u64).saturating_sub(stat.f_ffree as u64),
+        mount,
+    })
+}
+
+fn get_usages(filter: impl Fn(&Mount) -> bool) -> Vec<FilesystemUsage> {
+    get_mounts()
+        .into_iter()
+        .filter(filter)
+        .filter_map(|mount| get_filesystem_usage(mount).ok())
+        .collect()
+}
+
+/// Usage of every real local filesystem. Mounts we can't stat, e.g. without
+/// permission, are skipped.
+pub fn get_filesystems() -> Vec<FilesystemUsage> {
+    get_usages(|mount| !mount.is_network())
+}
+
+/// Usage of every network filesystem. This blocks as long as a server doesn't
+/// answer, so it shouldn't be called where a delay matters.
+pub fn get_remote_filesystems() -> Vec<FilesystemUsage> {
+    get_usages(Mount::is_network)
+}
+
+/// The filesystem with the highest space usage. Read-only filesystems, e.g. a
+/// mounted ISO, are always full so they are skipped, and so are network ones.
+pub fn fullest(filesystems: &[FilesystemUsage]) -> Option<&FilesystemUsage> {
+    filesystems
+        .iter()
+        .filter(|fs| !fs.mount.is_network() && !fs.mount.is_read_only())
+        .max_by(|a, b| a.usage().value.total_cmp(&b.usage().value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOUNTINFO: &str = "\
+22 28 0:20 / /proc rw,nosuid,nodev,noexec,relatime shared:12 - proc proc rw
+25 28 0:5 / /dev rw,nosuid shared:2 - devtmpfs devtmpfs rw,size=8041436k
+28 1 259:2 / / rw,relatime shared:1 - ext4 /dev/nvme0n1p2 rw
+31 28 259:1 / /boot/efi rw,relatime shared:15 - vfat /dev/nvme0n1p1 rw,fmask=0077
+40 28 0:35 / /tmp rw,nosuid,nodev shared:18 - tmpfs tmpfs rw
+52 28 7:0 / /snap/core/1234 ro,nodev,relatime shared:25 - squashfs /dev/loop0 ro
+60 28 8:17 / /media/usb\\040stick rw,relatime shared:30 master:1 - exfat /dev/sdb1 rw
+61 28 259:2 /home /home rw,relatime shared:1 - ext4 /dev/nvme0n1p2 rw";
+
+    #[test]
+    fn pseudo_and_duplicate_mounts_are_skipped() {
+        let mounts = parse_mountinfo(MOUNTINFO.lines().map(String::from));
+        let points: Vec<&Path> = mounts
+            .iter()
+            .map(|mount| mount.mount_point.as_path())
+            .collect();
+        assert_eq!(
+            points,
+            [
+                Path::new("/"),
+                Path::new("/boot/efi"),
+                Path::new("/media/usb stick")
+            ]
+        );
+        assert_eq!(mounts[2].fs_type, "exfat");
+        assert_eq!(mounts[2].source, "/dev/sdb1");
+        assert!(!mounts[2].read_only);
+        assert_eq!(unescape(r"a\134b\x"), r"a\b\x");
+    }
+
+    #[test]
+    fn network_and_read_only_filesystems_are_never_the_fullest() {
+        let lines = [
+            "28 1 259:2 / / rw,relatime shared:1 - ext4 /dev/nvme0n1p2 rw",
+            "70 28 0:50 / /mnt/nas rw,relatime shared:40 - nfs4 nas:/export rw",
+            "71 28 11:0 / /media/cdrom rw,relatime shared:41 - iso9660 /dev/sr0 rw",
+            "72 28 8:33 / /mnt/backup ro,relatime shared:42 - ext4 /dev/sdc1 ro",
+        ];
+        let filesystems: Vec<FilesystemUsage> =
+            parse_mountinfo(lines.iter().map(|l| l.to_string()))
+                .into_iter()
+                .enumerate()
+                .map(|(index, mount)| FilesystemUsage {
+                    mount,
+                    total: 100,
+                    used: 50 + index as u64 * 10,
+                    avail: 50 - index as u64 * 10,
+                    inodes_total: 0,
+                    inodes_used: 0,
+                })
+                .collect();
+        assert!(filesystems[1].mount.is_network());
+        assert!(filesystems[2].mount.is_read_only());
+        assert!(filesystems[3].mount.is_read_only());
+        let fullest = fullest(&filesystems).unwrap();
+        assert_eq!(fullest.mount.mount_point, Path::new("/"));
+    }
+
+    #[test]
+    fn usage_excludes_reserved_blocks() {
+        let usage = FilesystemUsage {
+            mount: Mount::parse(MOUNTINFO.lines().nth(2).unwrap()).unwrap(),
+            total: 100,
+            used: 45,
+            avail: 45,
+            inodes_total: 0,
+            inodes_used: 0,
+        };
+        assert_eq!(usage.usage().value, 0.5);
+        assert_eq!(usage.inode_usage().value, 0.0);
+
+        let temp = get_filesystem_usage(Mount {
+            mount_point: std::env::temp_dir(),
+            ..usage.mount
+        })
+        .unwrap();
+        assert!(temp.total > 0);
+        assert!(temp.used + temp.avail <= temp.total);
+    }
+}
@@ -1,10 +1,19 @@
+pub mod ambient_light;
 pub mod battery;
+pub mod battery_health;
 pub mod brightness;
 pub mod cpu;
+pub mod ddc;
 pub mod disk;
 pub mod fcitx;
+pub mod filesystem;
 pub mod keyboard_leds;
 pub mod memory;
 pub mod network;
+pub mod notification;
+pub mod pressure;
+pub mod process;
 pub mod pulseaudio;
+pub mod removable;
+pub mod thermal;
 pub mod util;
@@ -0,0 +1,46 @@
+use std::collections::HashMap;
+use zbus::dbus_proxy;
+use zvariant::Value;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Urgency {
+    Low = 0,
+    Normal = 1,
+    Critical = 2,
+}
+
+#[dbus_proxy(
+    interface = "org.freedesktop.Notifications",
+    default_service = "org.freedesktop.Notifications",
+    default_path = "/org/freedesktop/Notifications"
+)]
+pub trait Notifications {
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &self,
+        app_name: &str,
+        replaces_id: u32,
+        app_icon: &str,
+        summary: &str,
+        body: &str,
+        actions: &[&str],
+        hints: HashMap<&str, &Value<'_>>,
+        expire_timeout: i32,
+    ) -> zbus::Result<u32>;
+}
+
+/// Show a desktop notification, returns its ID. `icon` is a freedesktop icon
+/// name, e.g. `battery-caution`.
+pub fn send_notification(
+    icon: &str,
+    summary: &str,
+    body: &str,
+    urgency: Urgency,
+) -> zbus::Result<u32> {
+    let connection = zbus::blocking::Connection::session()?;
+    let proxy = NotificationsProxyBlocking::new(&connection)?;
+    let urgency = Value::U8(urgency as u8);
+    let hints = HashMap::from([("urgency", &urgency)]);
+    // -1 lets the notification server decide when to hide it
+    proxy.notify("obutils", 0, icon, summary, body, &[], hints, -1)
+}
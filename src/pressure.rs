@@ -0,0 +1,154 @@
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const PRESSURE_DIR: &str = "/proc/pressure";
+
+#[derive(thiserror::Error, Debug)]
+pub enum PressureError {
+    #[error("Malformed pressure line `{0}`")]
+    Malformed(String),
+    #[error("No `some` line in pressure of {0}")]
+    MissingSome(Resource),
+    #[error("IO error on {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+}
+
+/// A resource tracked by Pressure Stall Information, see
+/// https://docs.kernel.org/accounting/psi.html
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Resource {
+    Cpu,
+    Memory,
+    Io,
+}
+
+impl Display for Resource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Resource::Cpu => "cpu",
+            Resource::Memory => "memory",
+            Resource::Io => "io",
+        })
+    }
+}
+
+/// Share of time in percent some or all tasks were stalled on a resource
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct StallTime {
+    pub avg10: f64,
+    pub avg60: f64,
+    pub avg300: f64,
+    /// Total stall time in microseconds
+    pub total: u64,
+}
+
+impl StallTime {
+    /// Parse the fields after `some`/`full`, e.g. `avg10=1.50 avg60=0.80 avg300=0.20 total=123456`
+    fn parse(line: &str, fields: &[&str]) -> Result<StallTime, PressureError> {
+        let malformed = || PressureError::Malformed(line.to_string());
+        let mut result = StallTime::default();
+        for field in fields {
+            let (key, value) = field.split_once('=').ok_or_else(malformed)?;
+            match key {
+                "avg10" => result.avg10 = value.parse().map_err(|_| malformed())?,
+                "avg60" => result.avg60 = value.parse().map_err(|_| malformed())?,
+                "avg300" => result.avg300 = value.parse().map_err(|_| malformed())?,
+                "total" => result.total = value.parse().map_err(|_| malformed())?,
+                // Newer kernels may add fields
+                _ => {}
+            }
+        }
+        Ok(result)
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Pressure {
+    /// At least one task was stalled
+    pub some: StallTime,
+    /// All non-idle tasks were stalled at the same time. Older kernels
+    /// don't report it for the CPU.
+    pub full: Option<StallTime>,
+}
+
+impl Pressure {
+    fn parse(resource: Resource, content: &str) -> Result<Pressure, PressureError> {
+        let mut some = None;
+        let mut full = None;
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields[0] {
+                "some" => some = Some(StallTime::parse(line, &fields[1..])?),
+                "full" => full = Some(StallTime::parse(line, &fields[1..])?),
+                _ => return Err(PressureError::Malformed(line.to_string())),
+            }
+        }
+        Ok(Pressure {
+            some: some.ok_or(PressureError::MissingSome(resource))?,
+            full,
+        })
+    }
+}
+
+fn get_pressure_in(dir: &Path, resource: Resource) -> Result<Pressure, PressureError> {
+    let path = dir.join(resource.to_string());
+    let content = fs::read_to_string(&path).map_err(|e| PressureError::Io(path, e))?;
+    Pressure::parse(resource, &content)
+}
+
+/// Read `/proc/pressure/<resource>`, which needs a kernel built with `CONFIG_PSI`
+pub fn get_pressure(resource: Resource) -> Result<Pressure, PressureError> {
+    get_pressure_in(Path::new(PRESSURE_DIR), resource)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn some_and_full_are_parsed() {
+        let pressure = Pressure::parse(
+            Resource::Memory,
+            "some avg10=1.53 avg60=0.87 avg300=0.20 total=4181207\n\
+             full avg10=0.50 avg60=0.12 avg300=0.03 total=1519130\n",
+        )
+        .unwrap();
+        assert_eq!(
+            pressure.some,
+            StallTime {
+                avg10: 1.53,
+                avg60: 0.87,
+                avg300: 0.2,
+                total: 4181207,
+            }
+        );
+        assert_eq!(pressure.full.unwrap().total, 1519130);
+
+        let cpu = Pressure::parse(
+            Resource::Cpu,
+            "some avg10=0.00 avg60=0.00 avg300=0.00 total=0\n",
+        )
+        .unwrap();
+        assert_eq!(cpu.full, None);
+    }
+
+    #[test]
+    fn malformed_pressure_is_an_error() {
+        assert!(matches!(
+            Pressure::parse(Resource::Io, "some avg10=abc\n"),
+            Err(PressureError::Malformed(_))
+        ));
+        assert!(matches!(
+            Pressure::parse(
+                Resource::Io,
+                "full avg10=0.00 avg60=0.00 avg300=0.00 total=0\n"
+            ),
+            Err(PressureError::MissingSome(Resource::Io))
+        ));
+        assert!(matches!(
+            get_pressure_in(Path::new("/nonexistent"), Resource::Cpu),
+            Err(PressureError::Io(..))
+        ));
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::cpu::get_cpu_times;
+use crate::util::Percent;
+
+const PROC_DIR: &str = "/proc";
+
+/// A process as read from `/proc/[pid]/stat` and `/proc/[pid]/status`
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Process {
+    pub pid: u32,
+    /// Executable name, truncated to 15 characters by the kernel
+    pub name: String,
+    /// Time spent in user mode, in USER_HZ
+    pub utime: u64,
+    /// Time spent in kernel mode, in USER_HZ
+    pub stime: u64,
+    /// Resident set size in KiB, 0 for kernel threads
+    pub rss: u64,
+}
+
+impl Process {
+    /// Parse `/proc/[pid]/stat`. The name is between parentheses and can contain
+    /// spaces or parentheses itself, so the fields are counted from the last `)`.
+    fn parse_stat(pid: u32, stat: &str) -> Option<Process> {
+        let name_start = stat.find('(')? + 1;
+        let name_end = stat.rfind(')')?;
+        let fields: Vec<&str> = stat[name_end + 1..].split_whitespace().collect();
+        // Fields 14 and 15 in proc(5), counting from the state which is the 3rd
+        Some(Process {
+            pid,
+            name: stat[name_start..name_end].to_string(),
+            utime: fields.get(11)?.parse().ok()?,
+            stime: fields.get(12)?.parse().ok()?,
+            rss: 0,
+        })
+    }
+
+    fn parse_rss(status: &str) -> Option<u64> {
+        let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+        line["VmRSS:".len()..]
+            .trim()
+            .trim_end_matches("kB")
+            .trim()
+            .parse()
+            .ok()
+    }
+
+    fn read(dir: &Path, pid: u32) -> Option<Process> {
+        let dir = dir.join(pid.to_string());
+        let mut process = Process::parse_stat(pid, &fs::read_to_string(dir.join("stat")).ok()?)?;
+        if let Ok(status) = fs::read_to_string(dir.join("status")) {
+            process.rss = Process::parse_rss(&status).unwrap_or(0);
+        }
+        Some(process)
+    }
+
+    pub fn cpu_time(&self) -> u64 {
+        self.utime + self.stime
+    }
+}
+
+/// CPU share of a process between two snapshots, of all CPUs combined
+#[derive(Debug, Clone)]
+pub struct ProcessUsage<'a> {
+    pub process: &'a Process,
+    pub cpu: Percent,
+}
+
+/// Every process at a point in time, with the total CPU time to compare against
+#[derive(Debug, Default, Clone)]
+pub struct ProcessSnapshot {
+    /// Total time of all CPUs from `/proc/stat`, in USER_HZ
+    pub cpu_total: u64,
+    pub processes: HashMap<u32, Process>,
+}
+
+impl ProcessSnapshot {
+    fn take_in(dir: &Path, cpu_total: u64) -> ProcessSnapshot {
+        let pids = match fs::read_dir(dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<u32>().ok())
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        // Processes can exit while we read them, those are skipped
+        let processes = pids
+            .into_iter()
+            .filter_map(|pid| Some((pid, Process::read(dir, pid)?)))
+            .collect();
+        ProcessSnapshot {
+            cpu_total,
+            processes,
+        }
+    }
+
+    pub fn take() -> ProcessSnapshot {
+        ProcessSnapshot::take_in(Path::new(PROC_DIR), get_cpu_times().total.total())
+    }
+
+    /// The `n` processes using the most CPU between an older snapshot and this one.
+    /// Processes started in between are counted from their start.
+    pub fn top_cpu(&self, old: &ProcessSnapshot, n: usize) -> Vec<ProcessUsage<'_>> {
+        let total = self.cpu_total.saturating_sub(old.cpu_total);
+        if total == 0 {
+            return Vec::new();
+        }
+        let mut usages: Vec<ProcessUsage> = self
+            .processes
+            .values()
+            .map(|process| {
+                // A reused PID belongs to another process
+                let old_time = match old.processes.get(&process.pid) {
+                    Some(old) if old.name == process.name => old.cpu_time(),
+                    _ => 0,
+                };
+                let work = process.cpu_time().saturating_sub(old_time);
+                ProcessUsage {
+                    process,
+                    cpu: Percent::from(work as f64, total as f64),
+                }
+            })
+            .collect();
+        usages.sort_by(|a, b| {
+            b.cpu
+                .value
+                .total_cmp(&a.cpu.value)
+                .then(a.process.pid.cmp(&b.process.pid))
+        });
+        usages.truncate(n);
+        usages
+    }
+
+    /// The `n` processes with the largest resident set
+    pub fn top_rss(&self, n: usize) -> Vec<&Process> {
+        let mut processes: Vec<&Process> = self.processes.values().collect();
+        processes.sort_by(|a, b| b.rss.cmp(&a.rss).then(a.pid.cmp(&b.pid)));
+        processes.truncate(n);
+        processes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stat(pid: u32, name: &str, utime: u64, stime: u64) -> String {
+        format!(
+            "{} ({}) S 1 {} {} 0 -1 4194560 1234 0 0 0 {} {} 0 0 20 0 1 0 100 10000000 500",
+            pid, name, pid, pid, utime, stime
+        )
+    }
+
+    fn fake_proc(root: &Path, processes: &[(u32, &str, u64, u64, Option<u64>)]) {
+        let _ = fs::remove_dir_all(root);
+        for &(pid, name, utime, stime, rss) in processes {
+            let dir = root.join(pid.to_string());
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("stat"), stat(pid, name, utime, stime)).unwrap();
+            let status = match rss {
+                Some(rss) => format!("Name:\t{}\nVmRSS:\t  {} kB\nThreads:\t1\n", name, rss),
+                None => format!("Name:\t{}\nThreads:\t1\n", name),
+            };
+            fs::write(dir.join("status"), status).unwrap();
+        }
+        fs::create_dir_all(root.join("self")).unwrap();
+    }
+
+    #[test]
+    fn name_with_spaces_and_parentheses_is_parsed() {
+        let process = Process::parse_stat(42, &stat(42, "Web Content (x)", 150, 50)).unwrap();
+        assert_eq!(process.name, "Web Content (x)");
+        assert_eq!(process.utime, 150);
+        assert_eq!(process.stime, 50);
+        assert_eq!(Process::parse_rss("VmRSS:\t  2048 kB\n"), Some(2048));
+    }
+
+    #[test]
+    fn top_processes_by_cpu_and_rss() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("proc");
+        fake_proc(
+            &root,
+            &[
+                (1, "systemd", 100, 100, Some(12000)),
+                (2, "kthreadd", 0, 10, None),
+                (300, "firefox", 1000, 200, Some(900000)),
+                (400, "cargo", 500, 100, Some(300000)),
+            ],
+        );
+        let old = ProcessSnapshot::take_in(&root, 10000);
+        fake_proc(
+            &root,
+            &[
+                (1, "systemd", 100, 110, Some(12000)),
+                (2, "kthreadd", 0, 10, None),
+                (300, "firefox", 1100, 200, Some(950000)),
+                (400, "cargo", 800, 200, Some(300000)),
+                (500, "rustc", 50, 0, Some(400000)),
+            ],
+        );
+        let new = ProcessSnapshot::take_in(&root, 11000);
+
+        let top: Vec<(&str, f64)> = new
+            .top_cpu(&old, 3)
+            .iter()
+            .map(|usage| (usage.process.name.as_str(), usage.cpu.value))
+            .collect();
+        assert_eq!(top, [("cargo", 0.4), ("firefox", 0.1), ("rustc", 0.05)]);
+
+        let top: Vec<&str> = new
+            .top_rss(2)
+            .iter()
+            .map(|process| process.name.as_str())
+            .collect();
+        assert_eq!(top, ["firefox", "rustc"]);
+    }
+}
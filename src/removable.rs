@@ -0,0 +1,435 @@
+use notify::event::EventKind;
+use notify::{Config, PollWatcher, RecursiveMode, Watcher};
+use std::fs;
+use std::io;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use crate::filesystem::{get_mounts, Mount};
+
+const BLOCK_DIR: &str = "/sys/block";
+const BY_LABEL_DIR: &str = "/dev/disk/by-label";
+/// Multicast group of the uevents sent by the kernel, group 2 is for udev
+const KERNEL_UEVENT_GROUP: u32 = 1;
+const UEVENT_BUFFER_SIZE: usize = 8192;
+/// `size` in sysfs is in 512 bytes sectors, whatever the device
+const SECTOR_SIZE: u64 = 512;
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(thiserror::Error, Debug)]
+pub enum HotplugError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Watch {BLOCK_DIR}: {0}")]
+    Notify(#[from] notify::Error),
+}
+
+/// A removable drive, e.g. a USB stick or an SD card
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemovableDevice {
+    /// Name under `/sys/block`, e.g. `sdb` or `mmcblk0`
+    pub name: String,
+    /// Label of the first labelled partition, or of the device itself
+    pub label: Option<String>,
+    /// Size in bytes
+    pub size: u64,
+    pub partitions: Vec<String>,
+    pub mount_points: Vec<PathBuf>,
+}
+
+impl RemovableDevice {
+    pub fn is_mounted(&self) -> bool {
+        !self.mount_points.is_empty()
+    }
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path)
+        .ok()
+        .map(|content| content.trim().to_string())
+}
+
+/// Built-in card readers report their cards as not removable, and so do many
+/// USB drives, so the bus is checked too
+fn is_removable(path: &Path, name: &str) -> bool {
+    if name.starts_with("mmcblk") {
+        // Soldered eMMC system disks are `MMC`, along with their boot areas
+        return read_trimmed(&path.join("device/type")).as_deref() == Some("SD");
+    }
+    read_trimmed(&path.join("removable")).as_deref() == Some("1")
+        || fs::canonicalize(path).is_ok_and(|path| path.to_string_lossy().contains("/usb"))
+}
+
+/// Decode the `\x20` escapes udev uses in `/dev/disk/by-label`
+fn unescape_label(label: &str) -> String {
+    let mut bytes = Vec::with_capacity(label.len());
+    let mut rest = label.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let code = tail
+            .strip_prefix(b"x")
+            .and_then(|hex| std::str::from_utf8(hex.get(..2)?).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (byte, code) {
+            (b'\\', Some(code)) => {
+                bytes.push(code);
+                rest = &tail[3..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Map each device name to its label, from the `/dev/disk/by-label` symlinks
+fn read_labels(by_label_dir: &Path) -> Vec<(String, String)> {
+    let entries = match fs::read_dir(by_label_dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let target = fs::read_link(entry.path()).ok()?;
+            let device = target.file_name()?.to_str()?.to_string();
+            let label = unescape_label(entry.file_name().to_str()?);
+            Some((device, label))
+        })
+        .collect()
+}
+
+fn read_device(
+    path: &Path,
+    name: String,
+    labels: &[(String, String)],
+    mounts: &[Mount],
+) -> Option<RemovableDevice> {
+    let sectors: u64 = read_trimmed(&path.join("size"))?.parse().ok()?;
+    // A card reader without a card
+    if sectors == 0 {
+        return None;
+    }
+    let mut partitions: Vec<String> = fs::read_dir(path)
+        .ok()?
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            if !entry.path().join("partition").exists() {
+                return None;
+            }
+            entry.file_name().into_string().ok()
+        })
+        .collect();
+    partitions.sort();
+
+    let devices: Vec<&String> = partitions.iter().chain([&name]).collect();
+    let label = devices.iter().find_map(|device| {
+        labels
+            .iter()
+            .find(|(labelled, _)| labelled == *device)
+            .map(|(_, label)| label.clone())
+    });
+    let mount_points = mounts
+        .iter()
+        .filter(|mount| {
+            devices
+                .iter()
+                .any(|device| mount.source == format!("/dev/{}", device))
+        })
+        .map(|mount| mount.mount_point.clone())
+        .collect();
+    Some(RemovableDevice {
+        label,
+        size: sectors * SECTOR_SIZE,
+        partitions,
+        mount_points,
+        name,
+    })
+}
+
+fn list_removable_in(
+    block_dir: &Path,
+    by_label_dir: &Path,
+    mounts: &[Mount],
+) -> Vec<RemovableDevice> {
+    let mut names: Vec<String> = match fs::read_dir(block_dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    names.sort();
+    let labels = read_labels(by_label_dir);
+    names
+        .into_iter()
+        .filter(|name| is_removable(&block_dir.join(name), name))
+        .filter_map(|name| read_device(&block_dir.join(&name), name, &labels, mounts))
+        .collect()
+}
+
+/// Removable drives currently plugged in, sorted by name
+pub fn list_removable() -> Vec<RemovableDevice> {
+    list_removable_in(Path::new(BLOCK_DIR), Path::new(BY_LABEL_DIR), &get_mounts())
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BlockAction {
+    Add,
+    Remove,
+    /// Anything else, e.g. a card inserted into a reader
+    Change,
+}
+
+/// A block device or partition appeared, disappeared or changed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockEvent {
+    pub action: BlockAction,
+    pub name: String,
+}
+
+/// Parse a kernel uevent such as `add@/devices/...\0ACTION=add\0SUBSYSTEM=block\0DEVNAME=sdb\0...`,
+/// return `None` for other subsystems
+fn parse_uevent(message: &[u8]) -> Option<BlockEvent> {
+    let mut action = None;
+    let mut subsystem = None;
+    let mut name = None;
+    for field in message.split(|&byte| byte == 0).skip(1) {
+        let field = std::str::from_utf8(field).ok()?;
+        match field.split_once('=') {
+            Some(("ACTION", value)) => action = Some(value),
+            Some(("SUBSYSTEM", value)) => subsystem = Some(value),
+            Some(("DEVNAME", value)) => name = Some(value),
+            _ => {}
+        }
+    }
+    if subsystem != Some("block") {
+        return None;
+    }
+    let action = match action? {
+        "add" => BlockAction::Add,
+        "remove" => BlockAction::Remove,
+        _ => BlockAction::Change,
+    };
+    Some(BlockEvent {
+        action,
+        name: name?.to_string(),
+    })
+}
+
+enum Source {
+    Netlink(OwnedFd),
+    /// Inotify doesn't work on sysfs, so `/sys/block` is polled
+    Poll {
+        /// Polling stops when the watcher is dropped
+        _watcher: PollWatcher,
+        events: Receiver<notify::Result<notify::Event>>,
+    },
+}
+
+/// Wait for block devices to come and go
+pub struct HotplugWatcher {
+    source: Source,
+}
+
+fn open_netlink() -> io::Result<OwnedFd> {
+    // SAFETY: plain socket creation, the descriptor is owned right after
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+            libc::NETLINK_KOBJECT_UEVENT,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: fd is a valid descriptor nobody else owns
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    // SAFETY: sockaddr_nl is plain old data, and it outlives the bind call
+    let mut address: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+    address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+    address.nl_groups = KERNEL_UEVENT_GROUP;
+    let result = unsafe {
+        libc::bind(
+            fd.as_raw_fd(),
+            &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(fd)
+}
+
+impl HotplugWatcher {
+    /// Listen to kernel uevents, or poll `/sys/block` when netlink is not
+    /// available, e.g. in some containers
+    pub fn new() -> Result<Self, HotplugError> {
+        match open_netlink() {
+            Ok(fd) => Ok(HotplugWatcher {
+                source: Source::Netlink(fd),
+            }),
+            Err(_) => HotplugWatcher::poll(),
+        }
+    }
+
+    fn poll() -> Result<Self, HotplugError> {
+        let (tx, rx) = channel();
+        let config = Config::default().with_poll_interval(POLL_INTERVAL);
+        let mut watcher = PollWatcher::new(tx, config)?;
+        watcher.watch(Path::new(BLOCK_DIR), RecursiveMode::NonRecursive)?;
+        Ok(HotplugWatcher {
+            source: Source::Poll {
+                _watcher: watcher,
+                events: rx,
+            },
+        })
+    }
+
+    /// Wait up to `timeout` for a block device event
+    pub fn next_event(&mut self, timeout: Duration) -> Result<Option<BlockEvent>, HotplugError> {
+        match &self.source {
+            Source::Netlink(fd) => {
+                let mut poll_fd = libc::pollfd {
+                    fd: fd.as_raw_fd(),
+                    events: libc::POLLIN,
+                    revents: 0,
+                };
+                let timeout = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+                // SAFETY: poll_fd is valid for the duration of the call
+                if unsafe { libc::poll(&mut poll_fd, 1, timeout) } < 0 {
+                    return Err(io::Error::last_os_error().into());
+                }
+                if poll_fd.revents & libc::POLLIN == 0 {
+                    return Ok(None);
+                }
+                let mut buffer = [0u8; UEVENT_BUFFER_SIZE];
+                // SAFETY: the buffer is writable for its whole length
+                let len = unsafe {
+                    libc::recv(
+                        fd.as_raw_fd(),
+                        buffer.as_mut_ptr() as *mut libc::c_void,
+                        buffer.len(),
+                        0,
+                    )
+                };
+                if len < 0 {
+                    return Err(io::Error::last_os_error().into());
+                }
+                Ok(parse_uevent(&buffer[..len as usize]))
+            }
+            Source::Poll { events, .. } => {
+                let event = match events.recv_timeout(timeout) {
+                    Ok(event) => event?,
+                    Err(RecvTimeoutError::Timeout) => return Ok(None),
+                    Err(RecvTimeoutError::Disconnected) => {
+                        return Err(io::Error::from(io::ErrorKind::BrokenPipe).into())
+                    }
+                };
+                let action = match event.kind {
+                    EventKind::Create(_) => BlockAction::Add,
+                    EventKind::Remove(_) => BlockAction::Remove,
+                    _ => BlockAction::Change,
+                };
+                let name = event
+                    .paths
+                    .first()
+                    .and_then(|path| path.file_name())
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                Ok(Some(BlockEvent { action, name }))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn block_uevents_are_parsed() {
+        let message = b"add@/devices/pci0000:00/usb2/2-1/block/sdb\0ACTION=add\0\
+            DEVPATH=/devices/pci0000:00/usb2/2-1/block/sdb\0SUBSYSTEM=block\0\
+            DEVNAME=sdb\0DEVTYPE=disk\0SEQNUM=4321\0";
+        assert_eq!(
+            parse_uevent(message),
+            Some(BlockEvent {
+                action: BlockAction::Add,
+                name: "sdb".to_string()
+            })
+        );
+        let message =
+            b"remove@/devices/usb2/2-1\0ACTION=remove\0SUBSYSTEM=usb\0DEVNAME=bus/usb/002/003\0";
+        assert_eq!(parse_uevent(message), None);
+    }
+
+    #[test]
+    fn removable_devices_are_listed_with_label_and_mounts() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let block = root.join("block");
+        let sdb = block.join("sdb");
+        fs::create_dir_all(sdb.join("sdb1")).unwrap();
+        fs::write(sdb.join("removable"), "1\n").unwrap();
+        fs::write(sdb.join("size"), "62521344\n").unwrap();
+        fs::write(sdb.join("sdb1/partition"), "1\n").unwrap();
+        fs::create_dir_all(block.join("nvme0n1")).unwrap();
+        fs::write(block.join("nvme0n1/removable"), "0\n").unwrap();
+        fs::write(block.join("nvme0n1/size"), "1000215216\n").unwrap();
+        // A card reader without a card
+        fs::create_dir_all(block.join("sdc")).unwrap();
+        fs::write(block.join("sdc/removable"), "1\n").unwrap();
+        fs::write(block.join("sdc/size"), "0\n").unwrap();
+        // The eMMC system disk and its boot areas
+        for name in ["mmcblk0", "mmcblk0boot0", "mmcblk0boot1"] {
+            fs::create_dir_all(block.join(name).join("device")).unwrap();
+            fs::write(block.join(name).join("device/type"), "MMC\n").unwrap();
+            fs::write(block.join(name).join("size"), "8192\n").unwrap();
+        }
+        // A card in the built-in reader
+        fs::create_dir_all(block.join("mmcblk1/device")).unwrap();
+        fs::write(block.join("mmcblk1/device/type"), "SD\n").unwrap();
+        fs::write(block.join("mmcblk1/removable"), "0\n").unwrap();
+        fs::write(block.join("mmcblk1/size"), "31116288\n").unwrap();
+        let by_label = root.join("by-label");
+        fs::create_dir_all(&by_label).unwrap();
+        symlink("../../sdb1", by_label.join(r"USB\x20STICK")).unwrap();
+        symlink("../../nvme0n1p2", by_label.join("root")).unwrap();
+        let mounts = [Mount {
+            device: "8:17".to_string(),
+            mount_point: PathBuf::from("/media/usb"),
+            fs_type: "vfat".to_string(),
+            source: "/dev/sdb1".to_string(),
+            read_only: false,
+        }];
+
+        let devices = list_removable_in(&block, &by_label, &mounts);
+        assert_eq!(
+            devices,
+            [
+                RemovableDevice {
+                    name: "mmcblk1".to_string(),
+                    label: None,
+                    size: 31116288 * 512,
+                    partitions: Vec::new(),
+                    mount_points: Vec::new(),
+                },
+                RemovableDevice {
+                    name: "sdb".to_string(),
+                    label: Some("USB STICK".to_string()),
+                    size: 62521344 * 512,
+                    partitions: vec!["sdb1".to_string()],
+                    mount_points: vec![PathBuf::from("/media/usb")],
+                }
+            ]
+        );
+        assert!(!devices[0].is_mounted());
+        assert!(devices[1].is_mounted());
+    }
+}
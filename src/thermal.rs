@@ -0,0 +1,254 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const HWMON_DIR: &str = "/sys/class/hwmon";
+const THERMAL_DIR: &str = "/sys/class/thermal";
+
+/// Sensors reporting the temperature of the whole CPU package, as (hwmon name, label)
+const CPU_PACKAGE_SENSORS: [(&str, &str); 3] = [
+    ("k10temp", "Tctl"),
+    ("zenpower", "Tctl"),
+    ("coretemp", "Package id 0"),
+];
+/// Thermal zone types used for the CPU when no hwmon driver is loaded
+const CPU_THERMAL_ZONES: [&str; 2] = ["x86_pkg_temp", "cpu-thermal"];
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path)
+        .ok()
+        .map(|content| content.trim().to_string())
+}
+
+/// Temperatures in sysfs are in millidegree Celsius
+fn read_millidegrees(path: &Path) -> Option<f64> {
+    let value: i64 = read_trimmed(path)?.parse().ok()?;
+    Some(value as f64 / 1000.0)
+}
+
+/// Indices `N` of the files named `<prefix>N<suffix>` in a directory, sorted
+fn indices(dir: &Path, prefix: &str, suffix: &str) -> Vec<u32> {
+    let mut result: Vec<u32> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| {
+                let name = entry.ok()?.file_name();
+                name.to_str()?
+                    .strip_prefix(prefix)?
+                    .strip_suffix(suffix)?
+                    .parse()
+                    .ok()
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    result.sort_unstable();
+    result
+}
+
+/// A temperature sensor, either a hwmon `tempN_input` or a thermal zone
+#[derive(Debug, Clone, PartialEq)]
+pub struct TempSensor {
+    pub label: Option<String>,
+    /// File holding the current temperature
+    pub path: PathBuf,
+    /// Critical temperature in °C
+    pub crit: Option<f64>,
+}
+
+impl TempSensor {
+    /// Current temperature in °C, `None` if the sensor can't be read, e.g. a sleeping GPU
+    pub fn read(&self) -> Option<f64> {
+        read_millidegrees(&self.path)
+    }
+}
+
+/// A fan reported by a hwmon `fanN_input`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fan {
+    pub label: Option<String>,
+    pub path: PathBuf,
+}
+
+impl Fan {
+    pub fn read_rpm(&self) -> Option<u64> {
+        read_trimmed(&self.path)?.parse().ok()
+    }
+}
+
+/// A hardware monitoring chip under `/sys/class/hwmon`
+#[derive(Debug, Clone)]
+pub struct Hwmon {
+    /// Name of the driver, e.g. `k10temp` or `nvme`
+    pub name: String,
+    pub path: PathBuf,
+    pub temps: Vec<TempSensor>,
+    pub fans: Vec<Fan>,
+}
+
+impl Hwmon {
+    fn read(path: PathBuf) -> Option<Hwmon> {
+        let name = read_trimmed(&path.join("name"))?;
+        let temps = indices(&path, "temp", "_input")
+            .into_iter()
+            .map(|index| TempSensor {
+                label: read_trimmed(&path.join(format!("temp{}_label", index))),
+                path: path.join(format!("temp{}_input", index)),
+                crit: read_millidegrees(&path.join(format!("temp{}_crit", index))),
+            })
+            .collect();
+        let fans = indices(&path, "fan", "_input")
+            .into_iter()
+            .map(|index| Fan {
+                label: read_trimmed(&path.join(format!("fan{}_label", index))),
+                path: path.join(format!("fan{}_input", index)),
+            })
+            .collect();
+        Some(Hwmon {
+            name,
+            path,
+            temps,
+            fans,
+        })
+    }
+}
+
+fn list_hwmons_in(dir: &Path) -> Vec<Hwmon> {
+    indices(dir, "hwmon", "")
+        .into_iter()
+        .filter_map(|index| Hwmon::read(dir.join(format!("hwmon{}", index))))
+        .collect()
+}
+
+pub fn list_hwmons() -> Vec<Hwmon> {
+    list_hwmons_in(Path::new(HWMON_DIR))
+}
+
+/// The temperature of a thermal zone, labelled with its `type`.
+/// The critical temperature comes from the trip point of type `critical`.
+fn read_thermal_zone(path: PathBuf) -> TempSensor {
+    let crit = indices(&path, "trip_point_", "_type")
+        .into_iter()
+        .find(|index| {
+            read_trimmed(&path.join(format!("trip_point_{}_type", index))).as_deref()
+                == Some("critical")
+        })
+        .and_then(|index| read_millidegrees(&path.join(format!("trip_point_{}_temp", index))));
+    TempSensor {
+        label: read_trimmed(&path.join("type")),
+        path: path.join("temp"),
+        crit,
+    }
+}
+
+fn list_thermal_zones_in(dir: &Path) -> Vec<TempSensor> {
+    indices(dir, "thermal_zone", "")
+        .into_iter()
+        .map(|index| read_thermal_zone(dir.join(format!("thermal_zone{}", index))))
+        .collect()
+}
+
+/// Every `/sys/class/thermal/thermal_zone*`
+pub fn list_thermal_zones() -> Vec<TempSensor> {
+    list_thermal_zones_in(Path::new(THERMAL_DIR))
+}
+
+fn find_cpu_sensor_in(hwmon_dir: &Path, thermal_dir: &Path) -> Option<TempSensor> {
+    let hwmons = list_hwmons_in(hwmon_dir);
+    for (name, label) in CPU_PACKAGE_SENSORS {
+        let sensor = hwmons
+            .iter()
+            .filter(|hwmon| hwmon.name == name)
+            .flat_map(|hwmon| &hwmon.temps)
+            .find(|temp| temp.label.as_deref() == Some(label));
+        if let Some(sensor) = sensor {
+            return Some(sensor.clone());
+        }
+    }
+    list_thermal_zones_in(thermal_dir)
+        .into_iter()
+        .find(|zone| CPU_THERMAL_ZONES.contains(&zone.label.as_deref().unwrap_or_default()))
+}
+
+/// The sensor of the whole CPU package, `None` if there is no known one
+pub fn find_cpu_sensor() -> Option<TempSensor> {
+    find_cpu_sensor_in(Path::new(HWMON_DIR), Path::new(THERMAL_DIR))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_files(dir: &Path, files: &[(&str, &str)]) {
+        fs::create_dir_all(dir).unwrap();
+        for (name, content) in files {
+            fs::write(dir.join(name), content).unwrap();
+        }
+    }
+
+    #[test]
+    fn hwmon_sensors_and_fans_are_listed() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let hwmon = root.join("hwmon");
+        write_files(
+            &hwmon.join("hwmon0"),
+            &[
+                ("name", "nvme\n"),
+                ("temp1_input", "38850\n"),
+                ("temp1_label", "Composite\n"),
+                ("temp1_crit", "84850\n"),
+            ],
+        );
+        write_files(
+            &hwmon.join("hwmon1"),
+            &[
+                ("name", "k10temp\n"),
+                ("temp1_input", "61250\n"),
+                ("temp1_label", "Tctl\n"),
+                ("temp3_input", "52000\n"),
+                ("temp3_label", "Tccd1\n"),
+            ],
+        );
+        write_files(
+            &hwmon.join("hwmon2"),
+            &[("name", "thinkpad\n"), ("fan1_input", "2900\n")],
+        );
+
+        let hwmons = list_hwmons_in(&hwmon);
+        assert_eq!(hwmons.len(), 3);
+        assert_eq!(hwmons[0].temps[0].read(), Some(38.85));
+        assert_eq!(hwmons[0].temps[0].crit, Some(84.85));
+        assert_eq!(hwmons[1].temps[1].label.as_deref(), Some("Tccd1"));
+        assert_eq!(hwmons[2].fans[0].read_rpm(), Some(2900));
+
+        let cpu = find_cpu_sensor_in(&hwmon, &root.join("thermal")).unwrap();
+        assert_eq!(cpu.label.as_deref(), Some("Tctl"));
+        assert_eq!(cpu.read(), Some(61.25));
+    }
+
+    #[test]
+    fn thermal_zone_is_used_without_hwmon_driver() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let thermal = root.join("thermal");
+        write_files(
+            &thermal.join("thermal_zone0"),
+            &[("type", "acpitz\n"), ("temp", "27800\n")],
+        );
+        write_files(
+            &thermal.join("thermal_zone1"),
+            &[
+                ("type", "x86_pkg_temp\n"),
+                ("temp", "55000\n"),
+                ("trip_point_0_type", "passive\n"),
+                ("trip_point_0_temp", "95000\n"),
+                ("trip_point_1_type", "critical\n"),
+                ("trip_point_1_temp", "105000\n"),
+            ],
+        );
+
+        let cpu = find_cpu_sensor_in(&root.join("hwmon"), &thermal).unwrap();
+        assert_eq!(cpu.label.as_deref(), Some("x86_pkg_temp"));
+        assert_eq!(cpu.read(), Some(55.0));
+        assert_eq!(cpu.crit, Some(105.0));
+    }
+}
@@ -103,6 +103,28 @@ pub fn humanize(v: DataUnit, add_space: bool) -> String {
     }
 }
 
+/// Format a duration as hours and minutes, e.g. `2h13m`, or `13m` when less than an hour
+pub fn humanize_duration(dur: Duration) -> String {
+    let minutes = dur.as_secs() / 60;
+    if minutes < 60 {
+        format!("{}m", minutes)
+    } else {
+        format!("{}h{:02}m", minutes / 60, minutes % 60)
+    }
+}
+
+/// Draw each value as a block whose height follows the value, e.g. `▁▄█`
+pub fn sparkline(values: &[Percent]) -> String {
+    const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    values
+        .iter()
+        .map(|percent| {
+            let index = (percent.value.clamp(0.0, 1.0) * (BLOCKS.len() - 1) as f64).round();
+            BLOCKS[index as usize]
+        })
+        .collect()
+}
+
 pub fn read_lines(path: &str) -> impl Iterator<Item = String> {
     let f = File::open(path).unwrap_or_else(|_| panic!("Open \"{}\" for reading", path));
     BufReader::new(f)
@@ -126,7 +148,8 @@ pub fn flush_and_sleep(dur: Duration) {
 #[cfg(test)]
 mod tests {
     use crate::util::DataUnit::{Byte, KiB};
-    use crate::util::{humanize, is_integral};
+    use crate::util::{humanize, humanize_duration, is_integral, sparkline, Percent};
+    use std::time::Duration;
 
     #[test]
     fn is_integral_one_is_true() {
@@ -179,4 +202,27 @@ mod tests {
     fn humanize_right_align_value() {
         assert_eq!(humanize(Byte(0), true), "  0 B");
     }
+
+    #[test]
+    fn humanize_duration_shows_hours_and_minutes() {
+        assert_eq!(
+            humanize_duration(Duration::from_secs(2 * 3600 + 13 * 60 + 59)),
+            "2h13m"
+        );
+        assert_eq!(
+            humanize_duration(Duration::from_secs(3600 + 5 * 60)),
+            "1h05m"
+        );
+    }
+
+    #[test]
+    fn humanize_duration_omits_hours_when_less_than_one() {
+        assert_eq!(humanize_duration(Duration::from_secs(13 * 60)), "13m");
+    }
+
+    #[test]
+    fn sparkline_maps_values_to_block_heights() {
+        let values = [0.0, 0.5, 1.0, 1.5].map(Percent::from_normalized);
+        assert_eq!(sparkline(&values), "▁▅██");
+    }
 }